@@ -0,0 +1,164 @@
+use std::io::{self, Write};
+
+use crate::document::{Line, TapDocument};
+use crate::summary::Summary;
+
+/// Combines several TAP streams, such as one per CI shard, under a label for
+/// each source.
+#[derive(Default)]
+pub struct Aggregator<'a> {
+    sources: Vec<(String, &'a TapDocument<'a>)>,
+}
+
+impl<'a> Aggregator<'a> {
+    pub fn new() -> Self {
+        Aggregator::default()
+    }
+
+    pub fn add<L: Into<String>>(&mut self, label: L, document: &'a TapDocument<'a>) -> &mut Self {
+        self.sources.push((label.into(), document));
+        self
+    }
+
+    /// Each source's label and summary, in the order they were added.
+    pub fn summaries(&self) -> Vec<(&str, Summary)> {
+        self.sources
+            .iter()
+            .map(|(label, document)| (label.as_str(), Summary::from_document(document)))
+            .collect()
+    }
+
+    pub fn total(&self) -> Summary {
+        let summaries = self.summaries();
+        Summary::total(summaries.iter().map(|(_, summary)| summary))
+    }
+
+    /// Writes one TAP stream with a subtest per source, indented under a
+    /// `# Subtest: label` comment and closed by a test point that passes when
+    /// the source's summary is ok.
+    pub fn write_tap<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "1..{}", self.sources.len())?;
+        for (number, (label, document)) in self.sources.iter().enumerate() {
+            let label = label.replace(&['\r', '\n'][..], " ");
+            writeln!(out, "# Subtest: {}", label)?;
+            for line in &document.lines {
+                if let Line::Version(_) = line {
+                    continue;
+                }
+                for text in line.to_string().lines() {
+                    writeln!(out, "    {}", text)?;
+                }
+            }
+
+            let status = if Summary::from_document(document).is_ok() {
+                "ok"
+            } else {
+                "not ok"
+            };
+            // Escaped so a `#` in the label can't start a directive.
+            writeln!(
+                out,
+                "{} {} - {}",
+                status,
+                number + 1,
+                label.replace('#', "\\#")
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregate() {
+        let first = TapDocument::parse("TAP version 13\n1..2\nok 1\nok 2 # SKIP slow\n").unwrap();
+        let second =
+            TapDocument::parse("ok 1 - a\nnot ok 2 - b\n  ---\n  got: 1\n  ...\n1..2\n").unwrap();
+        let third = TapDocument::parse("1..0 # SKIP not on this platform\n").unwrap();
+
+        let mut aggregator = Aggregator::new();
+        aggregator
+            .add("shard #1", &first)
+            .add("shard 2", &second)
+            .add("shard 3", &third);
+
+        let summaries = aggregator.summaries();
+        assert_eq!(summaries.len(), 3);
+        assert_eq!(summaries[0].0, "shard #1");
+        assert!(summaries[0].1.is_ok());
+        assert_eq!(summaries[1].1.failed, 1);
+        assert_eq!(
+            summaries[2].1.skip_all.as_deref(),
+            Some("not on this platform")
+        );
+
+        let total = aggregator.total();
+        assert_eq!(total.planned, Some(4));
+        assert_eq!(total.tests, 4);
+        assert_eq!(total.passed, 3);
+        assert_eq!(total.failed, 1);
+        assert_eq!(total.skipped, 1);
+        assert!(total.plan_matches());
+        assert!(!total.is_ok());
+
+        let mut out = Vec::new();
+        aggregator.write_tap(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            r"1..3
+# Subtest: shard #1
+    1..2
+    ok 1
+    ok 2 # SKIP slow
+ok 1 - shard \#1
+# Subtest: shard 2
+    ok 1 - a
+    not ok 2 - b
+      ---
+      got: 1
+      ...
+    1..2
+not ok 2 - shard 2
+# Subtest: shard 3
+    1..0 # SKIP not on this platform
+ok 3 - shard 3
+"
+        );
+
+        let combined = TapDocument::parse(&out).unwrap();
+        let summary = Summary::from_document(&combined);
+        assert_eq!(summary.tests, 3);
+        assert_eq!(summary.failed, 1);
+        assert!(summary.plan_matches());
+
+        // Each label comes back as its test point's description, and each
+        // subtest, unindented, as the source's summary.
+        let labels: Vec<_> = combined
+            .tests()
+            .map(|test| test.description.as_deref().unwrap())
+            .collect();
+        assert_eq!(labels, ["shard #1", "shard 2", "shard 3"]);
+        let mut subtest = String::new();
+        let mut sources = summaries.iter();
+        for line in &combined.lines {
+            match line {
+                Line::Unknown(unknown) => {
+                    subtest += unknown.text.strip_prefix("    ").unwrap();
+                    subtest += "\n";
+                }
+                Line::Test(_) => {
+                    let source = &sources.next().unwrap().1;
+                    let text = std::mem::take(&mut subtest);
+                    let document = TapDocument::parse(&text).unwrap();
+                    assert_eq!(&Summary::from_document(&document), source);
+                }
+                _ => {}
+            }
+        }
+    }
+}
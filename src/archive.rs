@@ -0,0 +1,863 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::document::TapDocument;
+use crate::formatter::Formatter;
+use crate::harness::{self, FileResult, Stderr};
+use crate::limits::Limit;
+use crate::summary::Summary;
+
+/// A saved harness run in the layout of Perl's `TAP::Harness::Archive`: each
+/// program's TAP in a file named after it, and a `meta.yml` with the order
+/// the programs ran in, their timings and exit statuses.
+///
+/// ```text
+/// ---
+/// start_time: 1714557600.000000
+/// stop_time: 1714557601.250000
+/// file_order:
+///   - t/basic.t
+/// file_attributes:
+///   - description: t/basic.t
+///     start_time: 1714557600.000000
+///     end_time: 1714557601.250000
+///     exit: 0
+/// ```
+///
+/// Failed attempts at a file that was retried go next to it, in
+/// `t/basic.t.attempt-1` and so on, each with its own entry in
+/// `file_attributes` saying which `attempt` it was. A program's stderr goes
+/// in `t/basic.t.stderr`, with where each piece of it came among the TAP in
+/// its attributes, which also say if it `timed_out` or what limits it
+/// `exceeded`.
+///
+/// Paths are kept without any root, and with `..` resolved, so archives
+/// can't write outside themselves. Files that would end up in the same place
+/// can't be archived together.
+///
+/// Reading one back gives the same `FileResult`s, so a run can be reported,
+/// diffed or converted again without running the tests.
+#[derive(Clone, Debug)]
+pub struct Archive {
+    pub start_time: SystemTime,
+    pub stop_time: SystemTime,
+    pub results: Vec<FileResult>,
+}
+
+impl Archive {
+    /// The run that produced `results`, from when the first program started to
+    /// when the last one finished.
+    pub fn new(results: Vec<FileResult>) -> Self {
+        let now = SystemTime::now();
+        let start_time = results
+            .iter()
+            .map(|result| result.start_time)
+            .min()
+            .unwrap_or(now);
+        let stop_time = results
+            .iter()
+            .map(|result| result.start_time + result.duration)
+            .max()
+            .unwrap_or(now);
+
+        Archive {
+            start_time,
+            stop_time,
+            results,
+        }
+    }
+
+    /// Combines the archives of runs that split a suite between them, such
+    /// as the shards of a CI job, into one with the files in path order.
+    /// A file in more than one archive is an error.
+    pub fn merge<I: IntoIterator<Item = Archive>>(archives: I) -> io::Result<Archive> {
+        let mut results: Vec<FileResult> = archives
+            .into_iter()
+            .flat_map(|archive| archive.results)
+            .collect();
+        results.sort_by_cached_key(|result| archive_path(&result.path));
+        let same =
+            |pair: &&[FileResult]| archive_path(&pair[0].path) == archive_path(&pair[1].path);
+        if let Some(pair) = results.windows(2).find(same) {
+            return Err(invalid(format!(
+                "{} is in more than one archive",
+                pair[0].path.display()
+            )));
+        }
+        Ok(Archive::new(results))
+    }
+
+    pub fn write_dir<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        for (path, data) in self.files()? {
+            let path = dir.join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, data)?;
+        }
+        fs::write(dir.join("meta.yml"), self.meta())
+    }
+
+    pub fn read_dir<P: AsRef<Path>>(dir: P) -> io::Result<Archive> {
+        let dir = dir.as_ref();
+        let meta = fs::read_to_string(dir.join("meta.yml"))?;
+        Archive::read(&meta, |path| fs::read(dir.join(path)))
+    }
+
+    /// Writes the archive as a tarball, which can be compressed on its way to
+    /// `out`.
+    #[cfg(feature = "tar")]
+    pub fn write_tar<W: io::Write>(&self, out: W) -> io::Result<W> {
+        let mut builder = tar::Builder::new(out);
+        let mut append = |path: &Path, data: &[u8]| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(seconds(self.stop_time) as u64);
+            builder.append_data(&mut header, path, data)
+        };
+        for (path, data) in self.files()? {
+            append(&path, &data)?;
+        }
+        append(Path::new("meta.yml"), self.meta().as_bytes())?;
+
+        builder.into_inner()
+    }
+
+    /// Reads a tarball written by `write_tar`, compressed or not.
+    #[cfg(feature = "tar")]
+    pub fn read_tar<R: io::Read>(input: R) -> io::Result<Archive> {
+        use std::io::Read;
+
+        let mut files = HashMap::new();
+        let mut tarball = tar::Archive::new(crate::compress::decompress(input)?);
+        for entry in tarball.entries()? {
+            let mut entry = entry?;
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            files.insert(entry.path()?.into_owned(), data);
+        }
+
+        let meta = files
+            .remove(Path::new("meta.yml"))
+            .ok_or_else(|| invalid("no meta.yml in archive".to_string()))?;
+        Archive::read(&String::from_utf8_lossy(&meta), |path| {
+            files.remove(path).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} not in archive", path.display()),
+                )
+            })
+        })
+    }
+
+    /// Sends the saved run through `formatter` as if it were happening now.
+    pub fn replay(&self, formatter: &mut dyn Formatter) -> io::Result<()> {
+        for result in &self.results {
+            harness::replay(formatter, result)?;
+        }
+        formatter.finish(&self.results)
+    }
+
+    /// Each result and attempt's TAP, and stderr if it wrote any, with where
+    /// it goes in the archive. Two files that would go in the same place are
+    /// an error.
+    fn files(&self) -> io::Result<Vec<(PathBuf, Vec<u8>)>> {
+        let mut files = Vec::new();
+        let mut owners: HashMap<PathBuf, &Path> = HashMap::new();
+        owners.insert(PathBuf::from("meta.yml"), Path::new("meta.yml"));
+        for result in &self.results {
+            let attempts = result
+                .attempts
+                .iter()
+                .zip(1..)
+                .map(|(attempt, number)| (attempt_path(&result.path, number), attempt));
+            for (path, result) in
+                std::iter::once((archive_path(&result.path), result)).chain(attempts)
+            {
+                let stderr = stderr_path(&path);
+                for path in [&path, &stderr] {
+                    if let Some(owner) = owners.insert(path.clone(), &result.path) {
+                        return Err(invalid(format!(
+                            "{} and {} would both be archived as {}",
+                            owner.display(),
+                            result.path.display(),
+                            path.display()
+                        )));
+                    }
+                }
+                files.push((path, tap(result)));
+                if !result.stderr.is_empty() {
+                    let text: String = result
+                        .stderr
+                        .iter()
+                        .map(|stderr| stderr.text.as_str())
+                        .collect();
+                    files.push((stderr, text.into_bytes()));
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn meta(&self) -> String {
+        let mut meta = String::from("---\n");
+        meta += &format!("start_time: {:.6}\n", seconds(self.start_time));
+        meta += &format!("stop_time: {:.6}\n", seconds(self.stop_time));
+        meta += "file_order:\n";
+        for result in &self.results {
+            meta += &format!("  - {}\n", quote(&result.path.to_string_lossy()));
+        }
+        meta += "file_attributes:\n";
+        let attempts = self.results.iter().flat_map(|result| {
+            let attempts = result
+                .attempts
+                .iter()
+                .zip(1..)
+                .map(|(attempt, number)| (attempt, Some(number)));
+            std::iter::once((result, None)).chain(attempts)
+        });
+        for (result, attempt) in attempts {
+            meta += &format!(
+                "  - description: {}\n",
+                quote(&result.path.to_string_lossy())
+            );
+            if let Some(attempt) = attempt {
+                meta += &format!("    attempt: {}\n", attempt);
+            }
+            meta += &format!("    start_time: {:.6}\n", seconds(result.start_time));
+            meta += &format!(
+                "    end_time: {:.6}\n",
+                seconds(result.start_time + result.duration)
+            );
+            if let Some(code) = result.exit_status.code() {
+                meta += &format!("    exit: {}\n", code);
+            }
+            if let Some(signal) = signal(&result.exit_status) {
+                meta += &format!("    signal: {}\n", signal);
+            }
+            if result.timed_out {
+                meta += "    timed_out: true\n";
+            }
+            if !result.exceeded.is_empty() {
+                meta += "    exceeded:\n";
+                for limit in &result.exceeded {
+                    let (key, value) = limit_pair(limit);
+                    meta += &format!("      - {}: {}\n", key, value);
+                }
+            }
+            if !result.stderr.is_empty() {
+                meta += "    stderr:\n";
+                for stderr in &result.stderr {
+                    meta += &format!("      - line: {}\n", stderr.line);
+                    if let Some(test) = stderr.test {
+                        meta += &format!("        test: {}\n", test);
+                    }
+                    meta += &format!("        length: {}\n", stderr.text.len());
+                }
+            }
+        }
+        meta
+    }
+
+    fn read<F>(meta: &str, mut read_file: F) -> io::Result<Archive>
+    where
+        F: FnMut(&Path) -> io::Result<Vec<u8>>,
+    {
+        let meta = Meta::parse(meta)?;
+        let mut read_result = |path: &Path, file: &Path, attributes: &Attributes| {
+            let mut output = read_file(file)?;
+            let document = TapDocument::from_reader(&output[..])?;
+            // The test points added for limits weren't the program's output.
+            let added = document
+                .lines
+                .len()
+                .saturating_sub(attributes.exceeded.len());
+            if let Some(line) = document.lines.get(added) {
+                output.truncate(line.span().start);
+            }
+            let stderr = if attributes.stderr.is_empty() {
+                Vec::new()
+            } else {
+                let text = String::from_utf8_lossy(&read_file(&stderr_path(file))?).into_owned();
+                split_stderr(&text, &attributes.stderr)?
+            };
+            io::Result::Ok(FileResult {
+                path: path.to_path_buf(),
+                summary: Summary::from_document(&document),
+                document,
+                output,
+                exit_status: exit_status(attributes.exit, attributes.signal),
+                start_time: time(attributes.start_time)?,
+                // Clocks can step back while a program runs.
+                duration: duration((attributes.end_time - attributes.start_time).max(0.0))?,
+                timed_out: attributes.timed_out,
+                stderr,
+                exceeded: attributes.exceeded.clone(),
+                attempts: Vec::new(),
+            })
+        };
+
+        let mut results = Vec::new();
+        for description in &meta.file_order {
+            let path = PathBuf::from(description);
+            let for_file = |attributes: &&Attributes| &attributes.description == description;
+            let attributes = meta
+                .file_attributes
+                .iter()
+                .filter(for_file)
+                .find(|attributes| attributes.attempt.is_none())
+                .cloned()
+                .unwrap_or_default();
+            let mut result = read_result(&path, &archive_path(&path), &attributes)?;
+
+            let mut attempts: Vec<_> = meta
+                .file_attributes
+                .iter()
+                .filter(for_file)
+                .filter_map(|attributes| Some((attributes.attempt?, attributes)))
+                .collect();
+            attempts.sort_by_key(|(attempt, _)| *attempt);
+            for (attempt, attributes) in attempts {
+                let file = attempt_path(&path, attempt);
+                result.attempts.push(read_result(&path, &file, attributes)?);
+            }
+            results.push(result);
+        }
+
+        Ok(Archive {
+            start_time: time(meta.start_time)?,
+            stop_time: time(meta.stop_time)?,
+            results,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Meta {
+    start_time: f64,
+    stop_time: f64,
+    file_order: Vec<String>,
+    file_attributes: Vec<Attributes>,
+}
+
+#[derive(Clone, Default)]
+struct Attributes {
+    description: String,
+    start_time: f64,
+    end_time: f64,
+    exit: Option<i32>,
+    signal: Option<i32>,
+    /// Set for a failed attempt at a file that was retried.
+    attempt: Option<usize>,
+    timed_out: bool,
+    exceeded: Vec<Limit>,
+    stderr: Vec<Chunk>,
+}
+
+/// Where a piece of stderr came, and how many bytes of the stderr file it
+/// takes up.
+#[derive(Clone, Default)]
+struct Chunk {
+    line: usize,
+    test: Option<usize>,
+    length: usize,
+}
+
+impl Meta {
+    /// Reads back the subset of YAML that `Archive::meta` writes.
+    fn parse(text: &str) -> io::Result<Meta> {
+        let mut meta = Meta::default();
+        let mut section = "";
+        // The list in a file's attributes that deeper lines belong to.
+        let mut list = "";
+        for line in text.lines() {
+            if line.trim().is_empty() || line == "---" {
+                continue;
+            }
+
+            if !line.starts_with(' ') {
+                let (key, value) = line.split_once(':').unwrap_or((line, ""));
+                section = key.trim();
+                match section {
+                    "start_time" => meta.start_time = number(value.trim())?,
+                    "stop_time" => meta.stop_time = number(value.trim())?,
+                    _ => {}
+                }
+                continue;
+            }
+
+            let entry = line.trim_start();
+            let item = entry.strip_prefix("- ");
+            match section {
+                "file_order" => meta
+                    .file_order
+                    .extend(item.map(|item| unquote(item.trim()))),
+                "file_attributes" if line.len() - entry.len() > 4 => {
+                    let (attributes, (key, value)) = match (
+                        meta.file_attributes.last_mut(),
+                        item.unwrap_or(entry).split_once(':'),
+                    ) {
+                        (Some(attributes), Some(pair)) => (attributes, pair),
+                        _ => continue,
+                    };
+                    let value = value.trim();
+                    match (list, key.trim()) {
+                        ("exceeded", key) => attributes.exceeded.push(limit(key, value)?),
+                        ("stderr", key) => {
+                            if item.is_some() {
+                                attributes.stderr.push(Chunk::default());
+                            }
+                            let chunk = match attributes.stderr.last_mut() {
+                                Some(chunk) => chunk,
+                                None => continue,
+                            };
+                            match key {
+                                "line" => chunk.line = number(value)?,
+                                "test" => chunk.test = Some(number(value)?),
+                                "length" => chunk.length = number(value)?,
+                                _ => {}
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                "file_attributes" => {
+                    if item.is_some() {
+                        meta.file_attributes.push(Attributes::default());
+                    }
+                    let (attributes, (key, value)) = match (
+                        meta.file_attributes.last_mut(),
+                        item.unwrap_or(entry).split_once(':'),
+                    ) {
+                        (Some(attributes), Some(pair)) => (attributes, pair),
+                        _ => continue,
+                    };
+                    let value = value.trim();
+                    list = key.trim();
+                    match key.trim() {
+                        "description" => attributes.description = unquote(value),
+                        "start_time" => attributes.start_time = number(value)?,
+                        "end_time" => attributes.end_time = number(value)?,
+                        "exit" => attributes.exit = Some(number(value)?),
+                        "signal" => attributes.signal = Some(number(value)?),
+                        "attempt" => attributes.attempt = Some(number(value)?),
+                        "timed_out" => attributes.timed_out = value == "true",
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(meta)
+    }
+}
+
+pub(crate) fn number<T: std::str::FromStr>(value: &str) -> io::Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid(format!("{} is not a number", value)))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Plain YAML scalars stay as they are, anything else is single quoted.
+pub(crate) fn quote(text: &str) -> String {
+    let plain = !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_alphanumeric() || "_./-+".contains(c));
+    if plain {
+        text.to_string()
+    } else {
+        format!("'{}'", text.replace('\'', "''"))
+    }
+}
+
+pub(crate) fn unquote(text: &str) -> String {
+    match text
+        .strip_prefix('\'')
+        .and_then(|text| text.strip_suffix('\''))
+    {
+        Some(quoted) => quoted.replace("''", "'"),
+        None => text.to_string(),
+    }
+}
+
+/// What the archive keeps of a program: the bytes it printed, then the
+/// failing test points the harness added for limits it ran into.
+fn tap(result: &FileResult) -> Vec<u8> {
+    let lines = &result.document.lines;
+    if result.output.is_empty() {
+        return result.document.to_string().into_bytes();
+    }
+    let mut tap = result.output.clone();
+    let added = &lines[lines.len().saturating_sub(result.exceeded.len())..];
+    if !added.is_empty() && !tap.ends_with(b"\n") {
+        tap.push(b'\n');
+    }
+    for line in added {
+        tap.extend(format!("{}\n", line).bytes());
+    }
+    tap
+}
+
+/// Where a program's TAP goes inside the archive: its path without any root,
+/// and with each `..` taking away the directory before it, so archives can't
+/// write outside themselves.
+fn archive_path(path: &Path) -> PathBuf {
+    let mut archived = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => archived.push(part),
+            Component::ParentDir => {
+                archived.pop();
+            }
+            _ => {}
+        }
+    }
+    archived
+}
+
+/// Where the stderr of the program whose TAP is at `path` goes.
+fn stderr_path(path: &Path) -> PathBuf {
+    let mut file = path.as_os_str().to_owned();
+    file.push(".stderr");
+    PathBuf::from(file)
+}
+
+/// `text` cut back into the pieces `chunks` describe.
+fn split_stderr(mut text: &str, chunks: &[Chunk]) -> io::Result<Vec<Stderr>> {
+    let mut stderr = Vec::new();
+    for chunk in chunks {
+        if !text.is_char_boundary(chunk.length) {
+            return Err(invalid(format!(
+                "stderr doesn't split into pieces of {} bytes",
+                chunk.length
+            )));
+        }
+        let (piece, rest) = text.split_at(chunk.length);
+        stderr.push(Stderr {
+            line: chunk.line,
+            test: chunk.test,
+            text: piece.to_string(),
+        });
+        text = rest;
+    }
+    Ok(stderr)
+}
+
+fn limit_pair(limit: &Limit) -> (&'static str, u64) {
+    match *limit {
+        Limit::AddressSpace(bytes) => ("address_space", bytes),
+        Limit::Cpu(seconds) => ("cpu", seconds),
+        Limit::OpenFiles(count) => ("open_files", count),
+    }
+}
+
+fn limit(key: &str, value: &str) -> io::Result<Limit> {
+    let value = number(value)?;
+    match key {
+        "address_space" => Ok(Limit::AddressSpace(value)),
+        "cpu" => Ok(Limit::Cpu(value)),
+        "open_files" => Ok(Limit::OpenFiles(value)),
+        _ => Err(invalid(format!("{} is not a limit", key))),
+    }
+}
+
+/// Where the TAP of a file's failed `attempt`, counting from 1, goes.
+fn attempt_path(path: &Path, attempt: usize) -> PathBuf {
+    let mut file = archive_path(path).into_os_string();
+    file.push(format!(".attempt-{}", attempt));
+    PathBuf::from(file)
+}
+
+pub(crate) fn seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0.0, |since| since.as_secs_f64())
+}
+
+pub(crate) fn time(seconds: f64) -> io::Result<SystemTime> {
+    UNIX_EPOCH
+        .checked_add(duration(seconds)?)
+        .ok_or_else(|| invalid(format!("{} is out of range for a time", seconds)))
+}
+
+pub(crate) fn duration(seconds: f64) -> io::Result<Duration> {
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| invalid(format!("{} is out of range for a duration", seconds)))
+}
+
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+#[cfg(unix)]
+fn exit_status(code: Option<i32>, signal: Option<i32>) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    match signal {
+        Some(signal) => ExitStatus::from_raw(signal),
+        None => ExitStatus::from_raw(code.unwrap_or(0) << 8),
+    }
+}
+
+#[cfg(windows)]
+fn exit_status(code: Option<i32>, _signal: Option<i32>) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code.unwrap_or(0) as u32)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::document::Line;
+    use crate::formatter::{Quiet, Verbose};
+    use crate::harness::tests::{flaky, script};
+    use crate::harness::Harness;
+
+    // Spaced out, so a re-rendered document wouldn't match.
+    const PASS: &str = "1..2\nok  1\nok 2 - two # time=0.5s\n";
+
+    fn run(dir: &Path) -> Vec<FileResult> {
+        let files = [
+            script(dir, "pass.t", PASS, 0),
+            script(
+                dir,
+                "it's failing.t",
+                "1..2\nok 1\nnot ok 2 # TODO x\n# diag\n",
+                3,
+            ),
+        ];
+        Harness::new()
+            .formatter(Quiet::new(io::sink()))
+            .run(&files)
+            .unwrap()
+    }
+
+    /// `results` with stderr, a timeout and a limit run into, as a run could
+    /// have left them.
+    fn troubled(mut results: Vec<FileResult>) -> Vec<FileResult> {
+        let result = &mut results[1];
+        result.stderr = vec![
+            Stderr {
+                line: 0,
+                test: None,
+                text: "loading 'lib'\n".to_string(),
+            },
+            Stderr {
+                line: 3,
+                test: Some(2),
+                text: "caf\u{e9}\nfailed\n".to_string(),
+            },
+        ];
+        result.timed_out = true;
+        result.exceeded = vec![Limit::OpenFiles(8)];
+        let added = TapDocument::parse("not ok 3 - exceeded the open files limit of 8\n").unwrap();
+        result.document.lines.extend(added.into_owned().lines);
+        result.summary = Summary::from_document(&result.document);
+        results
+    }
+
+    fn assert_same(archive: &Archive, results: &[FileResult]) {
+        assert_eq!(archive.results.len(), results.len());
+        for (read, ran) in archive.results.iter().zip(results) {
+            assert_eq!(read.path, ran.path);
+            assert_eq!(read.output, ran.output);
+            assert_eq!(read.document.to_string(), ran.document.to_string());
+            assert_eq!(read.summary, ran.summary);
+            assert_eq!(read.exit_status, ran.exit_status);
+            assert_eq!(read.duration.as_millis(), ran.duration.as_millis());
+            assert_eq!(read.timed_out, ran.timed_out);
+            assert_eq!(read.stderr, ran.stderr);
+            assert_eq!(read.exceeded, ran.exceeded);
+            assert_eq!(read.attempts.len(), ran.attempts.len());
+            for (read, ran) in read.attempts.iter().zip(&ran.attempts) {
+                assert_eq!(read.output, ran.output);
+                assert_eq!(read.exit_status, ran.exit_status);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dir() {
+        let scripts = tempfile::tempdir().unwrap();
+        let results = troubled(run(scripts.path()));
+        let dir = tempfile::tempdir().unwrap();
+
+        Archive::new(results.clone()).write_dir(dir.path()).unwrap();
+        let meta = fs::read_to_string(dir.path().join("meta.yml")).unwrap();
+        assert!(meta.starts_with("---\nstart_time: "));
+        assert!(meta.contains("\n    exit: 3\n"));
+        assert!(meta.contains("it''s failing.t'\n"));
+        assert!(meta.contains(
+            "\n    timed_out: true\n    exceeded:\n      - open_files: 8\n    stderr:\n      \
+             - line: 0\n        length: 14\n      - line: 3\n        test: 2\n        length: 13\n"
+        ));
+        assert_eq!(
+            fs::read_to_string(dir.path().join(archive_path(&results[0].path))).unwrap(),
+            PASS
+        );
+        let failing = archive_path(&results[1].path);
+        assert!(fs::read_to_string(dir.path().join(&failing))
+            .unwrap()
+            .ends_with("# diag\nnot ok 3 - exceeded the open files limit of 8\n"));
+        assert_eq!(
+            fs::read_to_string(dir.path().join(stderr_path(&failing))).unwrap(),
+            "loading 'lib'\ncaf\u{e9}\nfailed\n"
+        );
+        assert!(!dir
+            .path()
+            .join(stderr_path(&archive_path(&results[0].path)))
+            .exists());
+
+        let archive = Archive::read_dir(dir.path()).unwrap();
+        assert_same(&archive, &results);
+        assert!(archive.start_time <= archive.stop_time);
+        assert!(matches!(
+            archive.results[1].document.lines.last(),
+            Some(Line::Test(test)) if !test.ok
+        ));
+
+        // Archiving what was read back changes nothing.
+        let again = tempfile::tempdir().unwrap();
+        archive.write_dir(again.path()).unwrap();
+        assert_same(&Archive::read_dir(again.path()).unwrap(), &results);
+    }
+
+    #[test]
+    fn test_attempts() {
+        let scripts = tempfile::tempdir().unwrap();
+        let results = Harness::new()
+            .formatter(Quiet::new(io::sink()))
+            .retries(2)
+            .run(&[flaky(scripts.path(), "flaky.t", 2)])
+            .unwrap();
+        assert_eq!(results[0].attempts.len(), 2);
+        let dir = tempfile::tempdir().unwrap();
+
+        Archive::new(results.clone()).write_dir(dir.path()).unwrap();
+        let attempt = archive_path(&results[0].path).with_extension("t.attempt-2");
+        assert_eq!(
+            fs::read_to_string(dir.path().join(attempt)).unwrap(),
+            "1..1\nnot ok 1\n"
+        );
+        let meta = fs::read_to_string(dir.path().join("meta.yml")).unwrap();
+        assert!(meta.contains("\n    attempt: 2\n"));
+
+        let archive = Archive::read_dir(dir.path()).unwrap();
+        assert_same(&archive, &results);
+        assert!(archive.results[0].is_flaky());
+        let mut out = Vec::new();
+        archive.replay(&mut Verbose::new(&mut out)).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("\nnot ok 1\nFailed 1/1 subtests, retrying\n"));
+    }
+
+    #[test]
+    fn test_replay() {
+        let scripts = tempfile::tempdir().unwrap();
+        let archive = Archive::new(run(scripts.path()));
+
+        let mut out = Vec::new();
+        archive.replay(&mut Verbose::new(&mut out)).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("\nnot ok 2 # TODO x\n# diag\nexit status: 3\n"));
+        assert!(text.ends_with("Files=2, Tests=4\n"));
+    }
+
+    #[test]
+    fn test_meta() {
+        let meta = Meta::parse(
+            "---\nstart_time: 10.5\nstop_time: 12\nfile_order:\n  - t/a.t\n  - 'b c.t'\nfile_attributes:\n  - description: t/a.t\n    start_time: 10.5\n    end_time: 11\n    exit: 0\n  - description: 'b c.t'\n    signal: 9\n",
+        )
+        .unwrap();
+
+        assert_eq!(meta.stop_time, 12.0);
+        assert_eq!(meta.file_order, vec!["t/a.t", "b c.t"]);
+        assert_eq!(meta.file_attributes[0].end_time, 11.0);
+        assert_eq!(meta.file_attributes[1].description, "b c.t");
+        assert_eq!(meta.file_attributes[1].signal, Some(9));
+        assert!(Meta::parse("start_time: soon\n").is_err());
+        for seconds in [-1.0, f64::INFINITY, f64::NAN, 1e300] {
+            assert_eq!(
+                time(seconds).unwrap_err().kind(),
+                io::ErrorKind::InvalidData
+            );
+        }
+        for path in ["/t/a.t", "t/a.t", "x/../t/a.t", "./t/./a.t", "/../../t/a.t"] {
+            assert_eq!(archive_path(Path::new(path)), Path::new("t/a.t"));
+        }
+    }
+
+    #[test]
+    fn test_collisions() {
+        let scripts = tempfile::tempdir().unwrap();
+        let results = run(scripts.path());
+        let at = |path: &str| {
+            let mut result = results[0].clone();
+            result.path = PathBuf::from(path);
+            result
+        };
+        let dir = tempfile::tempdir().unwrap();
+
+        for paths in [
+            ["/t/a.t", "t/a.t"],
+            ["t/a.t", "x/../t/a.t"],
+            ["t/a.t", "t/a.t.stderr"],
+            ["meta.yml", "t/a.t"],
+        ] {
+            let archive = Archive::new(paths.iter().map(|path| at(path)).collect());
+            let error = archive.write_dir(dir.path()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert!(error.to_string().contains("would both be archived as"));
+        }
+        assert!(!dir.path().join("meta.yml").exists());
+
+        let first = Archive::new(vec![at("/t/a.t")]);
+        let second = Archive::new(vec![at("t/a.t")]);
+        let error = Archive::merge(vec![first, second]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_merge() {
+        let scripts = tempfile::tempdir().unwrap();
+        let results = run(scripts.path());
+        let first = Archive::new(vec![results[1].clone()]);
+        let second = Archive::new(vec![results[0].clone()]);
+
+        let merged = Archive::merge(vec![first.clone(), second]).unwrap();
+        let mut sorted = results.clone();
+        sorted.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_same(&merged, &sorted);
+        assert!(merged.start_time <= first.start_time);
+
+        let error = Archive::merge(vec![first.clone(), first]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "tar")]
+    #[test]
+    fn test_tar() {
+        let scripts = tempfile::tempdir().unwrap();
+        let results = troubled(run(scripts.path()));
+
+        let tarball = Archive::new(results.clone()).write_tar(Vec::new()).unwrap();
+        assert_same(&Archive::read_tar(&tarball[..]).unwrap(), &results);
+    }
+}
@@ -0,0 +1,407 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::diff::Outcome;
+use crate::formatter::verdict;
+use crate::harness::FileResult;
+
+/// Failures a suite is known to have, so they don't break the build while
+/// new ones still do. One test per line, by number or by description:
+///
+/// ```text
+/// # Broken since the parser rewrite.
+/// t/parse.t 4
+/// t/net.t - resolves localhost
+/// ```
+///
+/// Descriptions survive tests being renumbered, so `from_results` prefers
+/// them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Baseline {
+    pub entries: Vec<Expected>,
+}
+
+/// A test expected to fail.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expected {
+    pub path: PathBuf,
+    pub test: TestId,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TestId {
+    Number(u64),
+    Description(String),
+}
+
+/// A failure found by `Baseline::check`. One without a number is the whole
+/// file going wrong, like a bailout or a bad plan, and its description is the
+/// file's verdict.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Failure {
+    pub path: PathBuf,
+    pub number: Option<u64>,
+    pub description: Option<String>,
+}
+
+/// How a run compares to its baseline.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BaselineReport {
+    /// Failures listed in the baseline.
+    pub known: Vec<Failure>,
+    /// Failures that aren't.
+    pub new: Vec<Failure>,
+    /// Entries for files that ran whose tests didn't fail, which should come
+    /// out of the baseline.
+    pub fixed: Vec<Expected>,
+}
+
+impl Baseline {
+    /// Errors name the file.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Baseline> {
+        let path = path.as_ref();
+        let error = |kind, err: &dyn fmt::Display| {
+            io::Error::new(kind, format!("{}: {}", path.display(), err))
+        };
+        fs::read_to_string(path)
+            .map_err(|err| error(err.kind(), &err))?
+            .parse()
+            .map_err(|err| error(io::ErrorKind::InvalidData, &err))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    /// A baseline expecting every test that failed in `results`.
+    pub fn from_results(results: &[FileResult]) -> Baseline {
+        let entries = results
+            .iter()
+            .flat_map(failures)
+            .filter_map(|failure| {
+                let test = match (failure.description, failure.number) {
+                    (Some(description), Some(_)) => TestId::Description(description),
+                    (None, Some(number)) => TestId::Number(number),
+                    (_, None) => return None,
+                };
+                Some(Expected {
+                    path: failure.path,
+                    test,
+                })
+            })
+            .collect();
+        Baseline { entries }
+    }
+
+    /// Sorts the failures in `results` into known and new, and finds the
+    /// entries that no longer fail. Entries for files that didn't run, as
+    /// when the suite is sharded, are left alone.
+    pub fn check(&self, results: &[FileResult]) -> BaselineReport {
+        let mut report = BaselineReport::default();
+        let mut matched = vec![false; self.entries.len()];
+        for failure in results.iter().flat_map(failures) {
+            let found = self
+                .entries
+                .iter()
+                .position(|expected| expected.matches(&failure));
+            match found {
+                Some(index) => {
+                    matched[index] = true;
+                    report.known.push(failure);
+                }
+                None => report.new.push(failure),
+            }
+        }
+        report.fixed = self
+            .entries
+            .iter()
+            .zip(matched)
+            .filter(|(expected, matched)| {
+                !matched && results.iter().any(|result| result.path == expected.path)
+            })
+            .map(|(expected, _)| expected.clone())
+            .collect();
+        report
+    }
+}
+
+impl Expected {
+    fn matches(&self, failure: &Failure) -> bool {
+        self.path == failure.path
+            && failure.number.is_some()
+            && match &self.test {
+                TestId::Number(number) => failure.number == Some(*number),
+                TestId::Description(description) => {
+                    failure.description.as_ref() == Some(description)
+                }
+            }
+    }
+}
+
+impl BaselineReport {
+    /// Nothing failed that the baseline didn't expect.
+    pub fn is_ok(&self) -> bool {
+        self.new.is_empty()
+    }
+}
+
+/// The failing test points of a file, then anything else wrong with it. An
+/// exit status the failures don't explain is wrong whatever the tests did.
+fn failures(result: &FileResult) -> Vec<Failure> {
+    let mut failures: Vec<Failure> = result
+        .document
+        .tests()
+        .enumerate()
+        .filter(|(_, test)| Outcome::of(test) == Outcome::Fail)
+        .map(|(index, test)| Failure {
+            path: result.path.clone(),
+            // Unnumbered test points count up from 1 in order.
+            number: Some(test.number.unwrap_or(index as u64 + 1)),
+            description: test.description.as_deref().map(str::to_string),
+        })
+        .collect();
+
+    let summary = &result.summary;
+    let failed_alone = summary.bailout.is_some()
+        || !summary.plan_matches()
+        || result.timed_out
+        || !result.exit_explained();
+    if failed_alone {
+        failures.push(Failure {
+            path: result.path.clone(),
+            number: None,
+            description: Some(verdict(result)),
+        });
+    }
+    failures
+}
+
+/// Parses the lines of a baseline file, skipping blank ones and `#`
+/// comments.
+impl FromStr for Baseline {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut baseline = Baseline::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (path, test) = match line.split_once(" - ") {
+                Some((path, description)) => (path, TestId::Description(description.to_string())),
+                None => {
+                    let error = || {
+                        format!(
+                            "line {}: needs a file and a test, not {:?}",
+                            index + 1,
+                            line
+                        )
+                    };
+                    let (path, number) = line.rsplit_once(' ').ok_or_else(error)?;
+                    (path, TestId::Number(number.parse().map_err(|_| error())?))
+                }
+            };
+            baseline.entries.push(Expected {
+                path: PathBuf::from(path.trim_end()),
+                test,
+            });
+        }
+        Ok(baseline)
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.test {
+            TestId::Number(number) => write!(f, "{} {}", self.path.display(), number),
+            TestId::Description(description) => {
+                write!(f, "{} - {}", self.path.display(), description)
+            }
+        }
+    }
+}
+
+/// One entry per line, in the format `FromStr` reads.
+impl fmt::Display for Baseline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for expected in &self.entries {
+            writeln!(f, "{}", expected)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(number) = self.number {
+            write!(f, " {}", number)?;
+        }
+        match &self.description {
+            Some(description) if self.number.is_some() => write!(f, " - {}", description),
+            Some(description) => write!(f, ": {}", description),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The failures grouped by kind, e.g.
+///
+/// ```text
+/// Known failures: 1
+///   t/parse.t 4 - nested lists
+/// New failures: 1
+///   t/net.t 2 - resolves localhost
+/// Passing, remove from the baseline: 1
+///   t/io.t 3
+/// ```
+impl fmt::Display for BaselineReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.known.is_empty() {
+            writeln!(f, "Known failures: {}", self.known.len())?;
+            for failure in &self.known {
+                writeln!(f, "  {}", failure)?;
+            }
+        }
+        if !self.new.is_empty() {
+            writeln!(f, "New failures: {}", self.new.len())?;
+            for failure in &self.new {
+                writeln!(f, "  {}", failure)?;
+            }
+        }
+        if !self.fixed.is_empty() {
+            writeln!(f, "Passing, remove from the baseline: {}", self.fixed.len())?;
+            for expected in &self.fixed {
+                writeln!(f, "  {}", expected)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::document::TapDocument;
+    use crate::summary::Summary;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::time::{Duration, SystemTime};
+
+    fn result(path: &str, tap: &str, code: i32) -> FileResult {
+        let document = TapDocument::parse(tap).unwrap().into_owned();
+        FileResult {
+            path: PathBuf::from(path),
+            summary: Summary::from_document(&document),
+            output: tap.as_bytes().to_vec(),
+            document,
+            exit_status: ExitStatus::from_raw(code << 8),
+            start_time: SystemTime::now(),
+            duration: Duration::ZERO,
+            timed_out: false,
+            stderr: Vec::new(),
+            exceeded: Vec::new(),
+            attempts: Vec::new(),
+        }
+    }
+
+    const BASELINE: &str = "# known
+t/a.t 2
+t/a.t - three
+
+t/b.t 1
+t/c.t - gone
+";
+
+    #[test]
+    fn test_parse() {
+        let baseline: Baseline = BASELINE.parse().unwrap();
+
+        assert_eq!(baseline.entries.len(), 4);
+        assert_eq!(baseline.entries[0].test, TestId::Number(2));
+        assert_eq!(
+            baseline.entries[1].test,
+            TestId::Description("three".to_string())
+        );
+        assert_eq!(baseline.to_string().parse(), Ok(baseline));
+        assert_eq!(
+            "t/a.t".parse::<Baseline>().unwrap_err(),
+            "line 1: needs a file and a test, not \"t/a.t\""
+        );
+        assert!("t/a.t x".parse::<Baseline>().is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let baseline: Baseline = BASELINE.parse().unwrap();
+        let results = [
+            result(
+                "t/a.t",
+                "1..4\nok 1\nnot ok 2 - two\nnot ok 3 - three\nnot ok 4 - four\n",
+                3,
+            ),
+            result("t/b.t", "1..1\nok 1\n", 0),
+            result("t/d.t", "1..2\nok 1\n", 0),
+        ];
+
+        let report = baseline.check(&results);
+        let show =
+            |failures: &[Failure]| failures.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(show(&report.known), ["t/a.t 2 - two", "t/a.t 3 - three"]);
+        assert_eq!(
+            show(&report.new),
+            ["t/a.t 4 - four", "t/d.t: Planned 2 but ran 1"]
+        );
+        // t/c.t didn't run, so may still be failing.
+        assert_eq!(report.fixed, [baseline.entries[2].clone()]);
+        assert!(!report.is_ok());
+        assert!(report
+            .to_string()
+            .ends_with("Passing, remove from the baseline: 1\n  t/b.t 1\n"));
+
+        let updated = Baseline::from_results(&results);
+        assert_eq!(
+            updated.to_string(),
+            "t/a.t - two\nt/a.t - three\nt/a.t - four\n"
+        );
+        assert!(updated.check(&results[..2]).is_ok());
+
+        let baseline: Baseline = "t/e.t 1\n".parse().unwrap();
+        let mut crashed = result("t/e.t", "1..1\nnot ok 1\n", 0);
+        crashed.exit_status = ExitStatus::from_raw(11);
+        let failed = |result: FileResult| baseline.check(&[result]).new;
+        assert!(failed(result("t/e.t", "1..1\nnot ok 1\n", 1)).is_empty());
+        assert_eq!(
+            show(&failed(result("t/e.t", "1..1\nnot ok 1\n", 2))),
+            ["t/e.t: Failed 1/1 subtests, exit status: 2"]
+        );
+        assert_eq!(
+            show(&failed(crashed)),
+            ["t/e.t: Failed 1/1 subtests, signal: 11 (SIGSEGV)"]
+        );
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("baseline");
+        let error = Baseline::load(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error
+            .to_string()
+            .starts_with(&format!("{}: ", path.display())));
+
+        fs::write(&path, "t/a.t\n").unwrap();
+        assert_eq!(
+            Baseline::load(&path).unwrap_err().to_string(),
+            format!(
+                "{}: line 1: needs a file and a test, not \"t/a.t\"",
+                path.display()
+            )
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Recognises the format from the first bytes of the input.
+    pub fn detect(magic: &[u8]) -> Compression {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Wraps `reader` so it yields plain TAP whether the input was gzip or zstd
+/// compressed or not compressed at all, for handing to `StreamParser`.
+///
+/// Decompression needs the `gzip` and `zstd` features. Without them,
+/// compressed input is an `Unsupported` error rather than garbage TAP.
+///
+/// ```
+/// use std::fs::File;
+/// use tap_pest::compress::decompress;
+/// use tap_pest::stream::StreamParser;
+///
+/// # fn run() -> std::io::Result<()> {
+/// for line in StreamParser::new(decompress(File::open("results.tap.gz")?)?) {
+///     println!("{}", line?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn decompress<'r, R: Read + 'r>(mut reader: R) -> io::Result<Box<dyn BufRead + 'r>> {
+    let mut magic = Vec::with_capacity(4);
+    reader.by_ref().take(4).read_to_end(&mut magic)?;
+    let compression = Compression::detect(&magic);
+    let reader = Cursor::new(magic).chain(reader);
+
+    match compression {
+        Compression::None => Ok(Box::new(BufReader::new(reader))),
+        Compression::Gzip => gzip(reader),
+        Compression::Zstd => zstd(reader),
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn gzip<'r, R: Read + 'r>(reader: R) -> io::Result<Box<dyn BufRead + 'r>> {
+    // Concatenated gzip files decompress to the concatenated contents.
+    Ok(Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(
+        reader,
+    ))))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gzip<'r, R: Read + 'r>(_reader: R) -> io::Result<Box<dyn BufRead + 'r>> {
+    Err(unsupported("gzip"))
+}
+
+#[cfg(feature = "zstd")]
+fn zstd<'r, R: Read + 'r>(reader: R) -> io::Result<Box<dyn BufRead + 'r>> {
+    Ok(Box::new(BufReader::new(zstd::stream::read::Decoder::new(
+        reader,
+    )?)))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd<'r, R: Read + 'r>(_reader: R) -> io::Result<Box<dyn BufRead + 'r>> {
+    Err(unsupported("zstd"))
+}
+
+#[cfg(not(all(feature = "gzip", feature = "zstd")))]
+fn unsupported(format: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "{} compressed input needs the `{}` feature of tap-pest",
+            format, format
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::TapDocument;
+
+    const EXAMPLES: [&str; 4] = [
+        "1..3\nok 1 - first\n# a comment\nok 2 # SKIP no network\nnot ok 3 # TODO later\n",
+        "ok 1 - retrieving servers\nnot ok 2 - pinged saphire\n  ---\n  got: 1\n  ...\n1..2\n",
+        "TAP version 13\n1..573\nnot ok 1 - database handle\nBail out! Couldn't connect\n",
+        "1..0 # SKIP no Windows\n",
+    ];
+
+    /// Parses `bytes` through `decompress`, checking it matches `example`.
+    fn check(bytes: &[u8], example: &str) {
+        let document = TapDocument::from_reader(decompress(bytes).unwrap()).unwrap();
+        assert_eq!(
+            document,
+            TapDocument::from_reader(example.as_bytes()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Compression::detect(b"\x1f\x8b\x08\x00"), Compression::Gzip);
+        assert_eq!(Compression::detect(b"\x28\xb5\x2f\xfd"), Compression::Zstd);
+        assert_eq!(Compression::detect(b"1..4"), Compression::None);
+        assert_eq!(Compression::detect(b""), Compression::None);
+    }
+
+    #[test]
+    fn test_uncompressed() {
+        for example in EXAMPLES.iter() {
+            check(example.as_bytes(), example);
+        }
+        check(b"ok", "ok");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        for example in EXAMPLES.iter() {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(example.as_bytes()).unwrap();
+            check(&encoder.finish().unwrap(), example);
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() {
+        for example in EXAMPLES.iter() {
+            check(&zstd::encode_all(example.as_bytes(), 0).unwrap(), example);
+        }
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn test_gzip_unsupported() {
+        let err = decompress(&b"\x1f\x8b\x08\x00\x00"[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,533 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use toml::{Table, Value};
+
+use crate::formatter::{Dots, Failures, Quiet, Verbose};
+use crate::gate::Gates;
+use crate::harness::Harness;
+use crate::limits::{bytes, Limits};
+use crate::shard::{Shard, ShardBy};
+use crate::source::{discover, Interpreter, Sources, DEFAULT_PATTERN};
+use crate::state::State;
+
+/// Where `tap-pest run` looks for its configuration.
+pub const CONFIG_FILE: &str = "tap-pest.toml";
+
+/// The formatters a config can name.
+pub const FORMATTERS: [&str; 4] = ["dots", "verbose", "quiet", "failures"];
+
+/// Project settings for the harness, like `prove`'s `.proverc`:
+///
+/// ```toml
+/// tests = ["t/**/*.t", "xt/*.t"]
+/// jobs = 4
+/// timeout = 30            # seconds
+/// formatter = "verbose"   # dots, verbose, quiet or failures
+/// archive = "tap-archive" # keep each run's TAP, see `Archive`
+/// baseline = "t/baseline" # known failures, see `Baseline`
+/// junit = "junit.xml"     # write the results as JUnit XML for CI
+/// sandbox = true          # run each test in a fresh temporary directory
+/// retries = 2             # rerun failing files, reporting flaky passes
+/// shard = "1/4"           # run the first quarter of the tests
+/// shard_by = "duration"   # hash or duration, see `Shard`
+///
+/// [gates]                 # what CI accepts, see `Gates`
+/// max_failures = 5
+/// min_pass_rate = 0.95
+/// forbid_todo_passed = true
+/// forbid_skips = true
+/// require_plan = true
+///
+/// [limits]                # Linux only
+/// address_space = "2G"
+/// cpu = 60                # seconds
+/// open_files = 256
+///
+/// [env]
+/// PERL5LIB = "lib"
+///
+/// [interpreters]
+/// py = "python3 -u"
+/// ```
+///
+/// Command line flags override the file through `set`, which takes the same
+/// keys, with `KEY=VALUE` for the tables.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    /// Globs for the test files, `t/**/*.t` if there are none.
+    pub tests: Vec<String>,
+    pub jobs: Option<usize>,
+    pub timeout: Option<Duration>,
+    /// One of `FORMATTERS`.
+    pub formatter: Option<String>,
+    /// Directory to write the run to as a TAP archive.
+    pub archive: Option<PathBuf>,
+    /// File listing the tests expected to fail.
+    pub baseline: Option<PathBuf>,
+    /// File to write the run to as JUnit XML.
+    pub junit: Option<PathBuf>,
+    pub env: BTreeMap<String, String>,
+    /// Command line to run files with each extension, e.g. `py = "python3 -u"`.
+    pub interpreters: BTreeMap<String, String>,
+    pub sandbox: bool,
+    pub limits: Limits,
+    pub gates: Gates,
+    /// How many times to rerun a failing file.
+    pub retries: usize,
+    /// The part of the tests to run, for splitting them across machines.
+    pub shard: Option<Shard>,
+    pub shard_by: ShardBy,
+}
+
+impl Config {
+    /// Reads a config file, or gives the defaults if there isn't one.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Config> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(text) => text.parse().map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), err),
+                )
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Overrides one setting from a command line flag. `tests` adds a glob,
+    /// `env`, `interpreters` and `limits` take `KEY=VALUE`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let pair = || match value.split_once('=') {
+            Some((key, value)) => Ok((key.to_string(), value.to_string())),
+            None => Err(format!("{} needs KEY=VALUE, not {:?}", key, value)),
+        };
+        match key {
+            "tests" => self.tests.push(value.to_string()),
+            "jobs" => self.jobs = Some(jobs(value.parse().ok())?),
+            "timeout" => self.timeout = Some(timeout(value.parse().ok())?),
+            "formatter" => self.formatter = Some(formatter(value)?),
+            "archive" => self.archive = Some(PathBuf::from(value)),
+            "baseline" => self.baseline = Some(PathBuf::from(value)),
+            "junit" => self.junit = Some(PathBuf::from(value)),
+            "env" => {
+                let (key, value) = pair()?;
+                self.env.insert(key, value);
+            }
+            "interpreters" => {
+                let (extension, command) = pair()?;
+                self.interpreters.insert(extension, command);
+            }
+            "sandbox" => {
+                self.sandbox = value
+                    .parse()
+                    .map_err(|_| "sandbox needs true or false".to_string())?
+            }
+            "retries" => self.retries = retries(value.parse().ok())?,
+            "shard" => self.shard = Some(value.parse()?),
+            "shard_by" => self.shard_by = value.parse()?,
+            "limits" => {
+                let (name, value) = pair()?;
+                limit(&mut self.limits, &name, &value)?;
+            }
+            "gates" => {
+                let (name, value) = pair()?;
+                gate(&mut self.gates, &name, &value)?;
+            }
+            _ => return Err(format!("unknown setting {:?}", key)),
+        }
+        Ok(())
+    }
+
+    /// The test files the globs match.
+    pub fn files(&self) -> io::Result<Vec<PathBuf>> {
+        if self.tests.is_empty() {
+            discover(&[DEFAULT_PATTERN])
+        } else {
+            discover(&self.tests)
+        }
+    }
+
+    /// The files among `files` in the configured shard, all of them without
+    /// one. `state` has the timings for `shard_by = "duration"`.
+    pub fn shard(&self, files: &[PathBuf], state: &State) -> Vec<PathBuf> {
+        match (self.shard, self.shard_by) {
+            (None, _) => files.to_vec(),
+            (Some(shard), ShardBy::Hash) => shard.select(files),
+            (Some(shard), ShardBy::Duration) => shard.balance(files, state),
+        }
+    }
+
+    /// A harness set up as configured.
+    pub fn harness(&self) -> Harness {
+        let mut sources = Sources::new();
+        for (extension, command) in &self.interpreters {
+            let mut words = command.split_whitespace();
+            if let Some(program) = words.next() {
+                let interpreter = words.fold(Interpreter::new(program), Interpreter::arg);
+                // Chosen for the project, so over any `#!` line.
+                sources = sources.handler(interpreter.extension(extension.as_str()).vote(1.0));
+            }
+        }
+
+        let mut harness = Harness::new().sources(sources);
+        harness = match self.formatter.as_deref() {
+            Some("verbose") => harness.formatter(Verbose::new(io::stdout())),
+            Some("quiet") => harness.formatter(Quiet::new(io::stdout())),
+            Some("failures") => harness.formatter(Failures::new(io::stdout())),
+            _ => harness.formatter(Dots::new(io::stdout())),
+        };
+        if let Some(jobs) = self.jobs {
+            harness = harness.jobs(jobs);
+        }
+        if let Some(timeout) = self.timeout {
+            harness = harness.timeout(timeout);
+        }
+        for (key, value) in &self.env {
+            harness = harness.env(key, value);
+        }
+        harness
+            .limits(self.limits)
+            .sandbox(self.sandbox)
+            .retries(self.retries)
+    }
+}
+
+impl FromStr for Config {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let table: Table = text
+            .parse()
+            .map_err(|err: toml::de::Error| err.message().to_string())?;
+
+        let mut config = Config::default();
+        for (key, value) in table {
+            match (key.as_str(), value) {
+                ("tests", Value::Array(globs)) => {
+                    for glob in globs {
+                        config.tests.push(string(&key, glob)?);
+                    }
+                }
+                ("tests", glob) => config.tests.push(string(&key, glob)?),
+                ("jobs", Value::Integer(value)) => {
+                    config.jobs = Some(jobs(usize::try_from(value).ok())?)
+                }
+                ("timeout", Value::Integer(value)) => {
+                    config.timeout = Some(timeout(Some(value as f64))?)
+                }
+                ("timeout", Value::Float(value)) => config.timeout = Some(timeout(Some(value))?),
+                ("formatter", value) => config.formatter = Some(formatter(&string(&key, value)?)?),
+                ("archive", value) => config.archive = Some(PathBuf::from(string(&key, value)?)),
+                ("baseline", value) => config.baseline = Some(PathBuf::from(string(&key, value)?)),
+                ("junit", value) => config.junit = Some(PathBuf::from(string(&key, value)?)),
+                ("env", Value::Table(table)) => {
+                    for (name, value) in table {
+                        config.env.insert(name, string(&key, value)?);
+                    }
+                }
+                ("interpreters", Value::Table(table)) => {
+                    for (extension, value) in table {
+                        config.interpreters.insert(extension, string(&key, value)?);
+                    }
+                }
+                ("sandbox", Value::Boolean(sandbox)) => config.sandbox = sandbox,
+                ("retries", Value::Integer(value)) => {
+                    config.retries = retries(usize::try_from(value).ok())?
+                }
+                ("shard", value) => config.shard = Some(string(&key, value)?.parse()?),
+                ("shard_by", value) => config.shard_by = string(&key, value)?.parse()?,
+                ("limits", Value::Table(table)) => {
+                    for (name, value) in table {
+                        let value = match value {
+                            Value::Integer(value) => value.to_string(),
+                            value => string(&key, value)?,
+                        };
+                        limit(&mut config.limits, &name, &value)?;
+                    }
+                }
+                ("gates", Value::Table(table)) => {
+                    for (name, value) in table {
+                        let value = match value {
+                            Value::Integer(value) => value.to_string(),
+                            Value::Float(value) => value.to_string(),
+                            Value::Boolean(value) => value.to_string(),
+                            _ => return Err(format!("gates.{} has the wrong type", name)),
+                        };
+                        gate(&mut config.gates, &name, &value)?;
+                    }
+                }
+                ("jobs", _)
+                | ("timeout", _)
+                | ("env", _)
+                | ("interpreters", _)
+                | ("sandbox", _)
+                | ("retries", _)
+                | ("limits", _)
+                | ("gates", _) => return Err(format!("{} has the wrong type", key)),
+                _ => return Err(format!("unknown setting {:?}", key)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn string(key: &str, value: Value) -> Result<String, String> {
+    match value {
+        Value::String(value) => Ok(value),
+        _ => Err(format!("{} needs strings", key)),
+    }
+}
+
+fn jobs(value: Option<usize>) -> Result<usize, String> {
+    value
+        .filter(|&jobs| jobs > 0)
+        .ok_or_else(|| "jobs needs a positive whole number".to_string())
+}
+
+fn retries(value: Option<usize>) -> Result<usize, String> {
+    value.ok_or_else(|| "retries needs a whole number".to_string())
+}
+
+fn timeout(seconds: Option<f64>) -> Result<Duration, String> {
+    seconds
+        .filter(|&seconds| seconds > 0.0)
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| "timeout needs a positive number of seconds".to_string())
+}
+
+/// Sets one of `Limits` from its name and a number, or a size like `512M`
+/// for `address_space`.
+fn limit(limits: &mut Limits, name: &str, value: &str) -> Result<(), String> {
+    let number = |parsed: Option<u64>| {
+        parsed
+            .filter(|&parsed| parsed > 0)
+            .ok_or_else(|| format!("limits.{} needs a positive number, not {:?}", name, value))
+    };
+    match name {
+        "address_space" => limits.address_space = Some(number(bytes(value))?),
+        "cpu" => limits.cpu = Some(number(value.parse().ok())?),
+        "open_files" => limits.open_files = Some(number(value.parse().ok())?),
+        _ => return Err(format!("unknown limit {:?}", name)),
+    }
+    Ok(())
+}
+
+/// Sets one of `Gates` from its name and a number or `true`/`false`.
+fn gate(gates: &mut Gates, name: &str, value: &str) -> Result<(), String> {
+    let flag = || {
+        value
+            .parse::<bool>()
+            .map_err(|_| format!("gates.{} needs true or false, not {:?}", name, value))
+    };
+    match name {
+        "max_failures" => {
+            gates.max_failures =
+                Some(value.parse().map_err(|_| {
+                    format!("gates.max_failures needs a whole number, not {:?}", value)
+                })?)
+        }
+        "min_pass_rate" => {
+            gates.min_pass_rate = Some(
+                value
+                    .parse()
+                    .ok()
+                    .filter(|rate| (0.0..=1.0).contains(rate))
+                    .ok_or_else(|| format!("gates.min_pass_rate needs 0 to 1, not {:?}", value))?,
+            )
+        }
+        "forbid_todo_passed" => gates.forbid_todo_passed = flag()?,
+        "forbid_skips" => gates.forbid_skips = flag()?,
+        "require_plan" => gates.require_plan = flag()?,
+        _ => return Err(format!("unknown gate {:?}", name)),
+    }
+    Ok(())
+}
+
+fn formatter(name: &str) -> Result<String, String> {
+    if FORMATTERS.contains(&name) {
+        Ok(name.to_string())
+    } else {
+        Err(format!(
+            "unknown formatter {:?}, expected one of {}",
+            name,
+            FORMATTERS.join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = r#"
+tests = ["t/**/*.t", "xt/*.t"]
+jobs = 4
+timeout = 2.5
+formatter = "verbose"
+archive = "tap-archive"
+baseline = "t/baseline"
+junit = "junit.xml"
+sandbox = true
+retries = 2
+shard = "2/3"
+shard_by = "duration"
+
+[gates]
+max_failures = 3
+min_pass_rate = 0.9
+require_plan = true
+
+[limits]
+address_space = "512M"
+cpu = 60
+
+[env]
+PERL5LIB = "lib"
+
+[interpreters]
+py = "python3 -u"
+"#;
+
+    #[test]
+    fn test_parse() {
+        let config: Config = EXAMPLE.parse().unwrap();
+
+        assert_eq!(config.tests, ["t/**/*.t", "xt/*.t"]);
+        assert_eq!(config.jobs, Some(4));
+        assert_eq!(config.timeout, Some(Duration::from_millis(2500)));
+        assert_eq!(config.formatter.as_deref(), Some("verbose"));
+        assert_eq!(config.archive, Some(PathBuf::from("tap-archive")));
+        assert_eq!(config.baseline, Some(PathBuf::from("t/baseline")));
+        assert_eq!(config.junit, Some(PathBuf::from("junit.xml")));
+        assert_eq!(config.env["PERL5LIB"], "lib");
+        assert_eq!(config.interpreters["py"], "python3 -u");
+        assert!(config.sandbox);
+        assert_eq!(config.retries, 2);
+        assert_eq!(
+            config.gates,
+            Gates::new()
+                .max_failures(3)
+                .min_pass_rate(0.9)
+                .require_plan()
+        );
+        assert_eq!(config.shard, Some(Shard { index: 2, count: 3 }));
+        assert_eq!(config.shard_by, ShardBy::Duration);
+        assert_eq!(
+            config.limits,
+            Limits::new().address_space(512 << 20).cpu(60)
+        );
+        assert_eq!("".parse::<Config>().unwrap(), Config::default());
+        assert_eq!(
+            "timeout = 3".parse::<Config>().unwrap().timeout,
+            Some(Duration::from_secs(3))
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        let error = |text: &str| text.parse::<Config>().unwrap_err();
+
+        assert_eq!(error("color = true"), "unknown setting \"color\"");
+        assert_eq!(error("jobs = 0"), "jobs needs a positive whole number");
+        assert_eq!(error("jobs = \"4\""), "jobs has the wrong type");
+        assert_eq!(error("tests = [1]"), "tests needs strings");
+        assert_eq!(error("sandbox = 1"), "sandbox has the wrong type");
+        assert_eq!(error("retries = -1"), "retries needs a whole number");
+        assert!(error("shard = \"4/3\"").starts_with("shard needs i/n"));
+        assert_eq!(error("[limits]\nstack = 1"), "unknown limit \"stack\"");
+        assert_eq!(error("[gates]\nmax_time = 1"), "unknown gate \"max_time\"");
+        assert_eq!(error("gates = 1"), "gates has the wrong type");
+        assert!(error("[gates]\nmin_pass_rate = 95").starts_with("gates.min_pass_rate needs"));
+        assert!(error("[gates]\nforbid_skips = 1").starts_with("gates.forbid_skips needs"));
+        assert_eq!(
+            error("[limits]\naddress_space = \"lots\""),
+            "limits.address_space needs a positive number, not \"lots\""
+        );
+        assert!(error("formatter = \"tap\"").starts_with("unknown formatter \"tap\""));
+        assert!(error("timeout = -1").starts_with("timeout needs"));
+        assert!("jobs = ".parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn test_set() {
+        let mut config: Config = EXAMPLE.parse().unwrap();
+        config.set("jobs", "8").unwrap();
+        config.set("timeout", "1").unwrap();
+        config.set("formatter", "quiet").unwrap();
+        config.set("junit", "out/junit.xml").unwrap();
+        config.set("env", "PERL5LIB=blib").unwrap();
+        config.set("interpreters", "rb=ruby -w").unwrap();
+        config.set("sandbox", "false").unwrap();
+        config.set("retries", "0").unwrap();
+        config.set("shard", "1/2").unwrap();
+        config.set("shard_by", "hash").unwrap();
+        config.set("limits", "open_files=64").unwrap();
+        config.set("gates", "forbid_skips=true").unwrap();
+        config.set("gates", "max_failures=0").unwrap();
+
+        assert_eq!(config.jobs, Some(8));
+        assert_eq!(config.timeout, Some(Duration::from_secs(1)));
+        assert_eq!(config.formatter.as_deref(), Some("quiet"));
+        assert_eq!(config.junit, Some(PathBuf::from("out/junit.xml")));
+        assert_eq!(config.env["PERL5LIB"], "blib");
+        assert_eq!(config.interpreters["rb"], "ruby -w");
+        assert!(!config.sandbox);
+        assert_eq!(config.retries, 0);
+        assert_eq!(config.shard, Some(Shard { index: 1, count: 2 }));
+        assert_eq!(config.shard_by, ShardBy::Hash);
+        assert_eq!(config.limits.open_files, Some(64));
+        assert!(config.gates.forbid_skips);
+        assert_eq!(config.gates.max_failures, Some(0));
+        assert!(config.gates.require_plan);
+        assert_eq!(config.limits.cpu, Some(60));
+        assert!(config.set("jobs", "many").is_err());
+        assert!(config.set("env", "PERL5LIB").is_err());
+        assert!(config.set("colour", "always").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+
+        fs::write(&path, "jobs = [").unwrap();
+        let err = Config::load(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with(&path.display().to_string()));
+
+        let test = dir.path().join("echo.t");
+        // The configured interpreter beats the `#!` line.
+        fs::write(&test, "#!/bin/false\necho 1..1\necho \"ok 1 - $WHO\"\n").unwrap();
+        let mut config = Config::default();
+        config
+            .set("tests", &format!("{}/*.t", dir.path().display()))
+            .unwrap();
+        config.set("interpreters", "t=sh -e").unwrap();
+        config.set("env", "WHO=me").unwrap();
+
+        let files = config.files().unwrap();
+        assert_eq!(files, [test]);
+        let results = config
+            .harness()
+            .formatter(Quiet::new(io::sink()))
+            .run(&files)
+            .unwrap();
+        let description = results[0]
+            .document
+            .tests()
+            .next()
+            .unwrap()
+            .description
+            .clone();
+        assert_eq!(description.as_deref(), Some("me"));
+    }
+}
@@ -0,0 +1,330 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use pest::error::{Error, ErrorVariant};
+use pest::iterators::Pair;
+use pest::{Parser, Position};
+
+use crate::tap_parser::{Rule, TapParser};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// One line of input, YAML blocks included, with its line ending.
+    Line,
+    Version,
+    Plan,
+    Test,
+    Comment,
+    Bailout,
+    Yaml,
+    Unknown,
+    Status,
+    Number,
+    Description,
+    Directive,
+    /// node-tap's `# time=12.3ms`.
+    Time,
+    /// Free text, like a comment's text or a directive's reason.
+    Text,
+    /// Bytes the grammar matches without a rule of their own, like the
+    /// ` - ` before a description or the spaces after `#`.
+    Trivia,
+    Newline,
+}
+
+/// A node in the concrete syntax tree.
+///
+/// Unlike `TapDocument`, nothing is left out: the texts of a node's children
+/// add up to its own text. Nodes without children are tokens.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node<'a> {
+    pub kind: Kind,
+    pub text: Cow<'a, str>,
+    pub children: Vec<Node<'a>>,
+}
+
+impl<'a> Node<'a> {
+    /// The first descendant of the given kind, depth first.
+    pub fn find(&self, kind: Kind) -> Option<&Node<'a>> {
+        self.children.iter().find_map(|child| {
+            if child.kind == kind {
+                Some(child)
+            } else {
+                child.find(kind)
+            }
+        })
+    }
+
+    /// The line ending this node finishes with, if any.
+    pub fn newline(&self) -> &str {
+        newline(&self.text)
+    }
+
+    pub fn into_owned(self) -> Node<'static> {
+        Node {
+            kind: self.kind,
+            text: Cow::Owned(self.text.into_owned()),
+            children: self.children.into_iter().map(Node::into_owned).collect(),
+        }
+    }
+}
+
+/// Lossless parse of a TAP stream, for tools that edit some lines and write
+/// the rest back exactly as they were.
+///
+/// ```
+/// use tap_pest::cst::Cst;
+///
+/// let mut cst = Cst::parse("1..2\nok 1 -  first\nnot ok 2 - second\r\n").unwrap();
+/// cst.replace(2, "ok 2 - second").unwrap();
+/// assert_eq!(cst.to_string(), "1..2\nok 1 -  first\nok 2 - second\r\n");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cst<'a> {
+    /// `Kind::Line` nodes, in input order. Blank lines are kept too.
+    pub lines: Vec<Node<'a>>,
+}
+
+impl<'a> Cst<'a> {
+    pub fn parse(input: &'a str) -> Result<Cst<'a>, Error<Rule>> {
+        let tap = TapParser::parse(Rule::tap, input)?
+            .next()
+            .expect("tap rule always produces a pair");
+
+        let mut pairs = tap
+            .into_inner()
+            .filter(|pair| pair.as_rule() != Rule::EOI)
+            .peekable();
+        let mut lines = Vec::new();
+        let mut start = 0;
+        while start < input.len() {
+            let mut end = line_end(input, start);
+            let mut line = Vec::new();
+            while let Some(pair) = pairs.next_if(|pair| pair.as_span().start() < end) {
+                // YAML blocks run over several lines.
+                if pair.as_span().end() > end {
+                    end = line_end(input, pair.as_span().end());
+                }
+                line.push(pair);
+            }
+
+            lines.push(line_node(input, start, end, line));
+            start = end;
+        }
+
+        Ok(Cst { lines })
+    }
+
+    /// Replaces the line at `index` with `text`, which must parse as a single
+    /// line of TAP (or one YAML block). Without a line ending of its own, it
+    /// keeps the one the old line had. Fails if there's no line at `index`.
+    pub fn replace(&mut self, index: usize, text: &str) -> Result<(), Error<Rule>> {
+        let Some(old) = self.lines.get(index) else {
+            return Err(Error::new_from_pos(
+                ErrorVariant::CustomError {
+                    message: format!("there is no line {} of {}", index, self.lines.len()),
+                },
+                Position::from_start(text),
+            ));
+        };
+        let mut text = text.to_string();
+        if newline(&text).is_empty() {
+            text.push_str(old.newline());
+        }
+
+        let pairs = TapParser::parse(Rule::stream_line, &text)?
+            .filter(|pair| pair.as_rule() != Rule::EOI)
+            .collect();
+        self.lines[index] = line_node(&text, 0, text.len(), pairs).into_owned();
+        Ok(())
+    }
+
+    pub fn into_owned(self) -> Cst<'static> {
+        Cst {
+            lines: self.lines.into_iter().map(Node::into_owned).collect(),
+        }
+    }
+}
+
+/// Writes back exactly the text that was parsed, with any replaced lines.
+impl<'a> fmt::Display for Cst<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            f.write_str(&line.text)?;
+        }
+        Ok(())
+    }
+}
+
+fn newline(text: &str) -> &str {
+    ["\r\n", "\n", "\r"]
+        .iter()
+        .find(|newline| text.ends_with(*newline))
+        .map_or("", |newline| newline)
+}
+
+/// Offset just past the line ending following `start`.
+fn line_end(input: &str, start: usize) -> usize {
+    input[start..]
+        .find('\n')
+        .map_or(input.len(), |newline| start + newline + 1)
+}
+
+fn line_node<'a>(input: &'a str, start: usize, end: usize, pairs: Vec<Pair<'a, Rule>>) -> Node<'a> {
+    Node {
+        kind: Kind::Line,
+        text: Cow::Borrowed(&input[start..end]),
+        children: children(input, start, end, pairs),
+    }
+}
+
+fn node<'a>(input: &'a str, pair: Pair<'a, Rule>) -> Node<'a> {
+    let kind = match pair.as_rule() {
+        Rule::version => Kind::Version,
+        Rule::plan => Kind::Plan,
+        Rule::test => Kind::Test,
+        Rule::comment => Kind::Comment,
+        Rule::bailout => Kind::Bailout,
+        Rule::yaml => Kind::Yaml,
+        Rule::unknown | Rule::noplan_unknown => Kind::Unknown,
+        Rule::status => Kind::Status,
+        Rule::positiveInteger | Rule::nonNegativeInteger | Rule::time_value => Kind::Number,
+        Rule::desc_text => Kind::Description,
+        Rule::todo_directive | Rule::skip_directive => Kind::Directive,
+        Rule::time => Kind::Time,
+        Rule::text_output | Rule::yaml_text => Kind::Text,
+        rule => unreachable!("unexpected {:?} in tap", rule),
+    };
+    let span = pair.as_span();
+    let inner: Vec<_> = pair.into_inner().collect();
+    let children = if inner.is_empty() {
+        Vec::new()
+    } else {
+        children(input, span.start(), span.end(), inner)
+    };
+
+    Node {
+        kind,
+        text: Cow::Borrowed(span.as_str()),
+        children,
+    }
+}
+
+/// Nodes for `pairs`, with whatever lies between them in `start..end` as
+/// trivia and newline tokens.
+fn children<'a>(
+    input: &'a str,
+    start: usize,
+    end: usize,
+    pairs: Vec<Pair<'a, Rule>>,
+) -> Vec<Node<'a>> {
+    let mut children = Vec::new();
+    let mut offset = start;
+    for pair in pairs {
+        gap(&input[offset..pair.as_span().start()], &mut children);
+        offset = pair.as_span().end();
+        children.push(node(input, pair));
+    }
+    gap(&input[offset..end], &mut children);
+    children
+}
+
+fn gap<'a>(mut text: &'a str, children: &mut Vec<Node<'a>>) {
+    while !text.is_empty() {
+        let newline = ["\r\n", "\n", "\r"]
+            .iter()
+            .find(|newline| text.starts_with(*newline));
+        let (kind, len) = match newline {
+            Some(newline) => (Kind::Newline, newline.len()),
+            None => (
+                Kind::Trivia,
+                text.find(&['\r', '\n'][..]).unwrap_or(text.len()),
+            ),
+        };
+        children.push(Node {
+            kind,
+            text: Cow::Borrowed(&text[..len]),
+            children: Vec::new(),
+        });
+        text = &text[len..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens<'n>(node: &'n Node, out: &mut Vec<(Kind, &'n str)>) {
+        if node.children.is_empty() {
+            out.push((node.kind, &node.text));
+        }
+        for child in &node.children {
+            tokens(child, out);
+        }
+    }
+
+    #[test]
+    fn test_lossless() {
+        let input = "TAP version 13\r\n1..3\n\n#   indented comment\nok 1 - spaced  # todo  later\n  ---\n  got: 1\n  ...\nnot ok 2\nsome noise\n";
+        let cst = Cst::parse(input).unwrap();
+
+        assert_eq!(cst.to_string(), input);
+        assert_eq!(cst.lines.len(), 8);
+        for line in &cst.lines {
+            let text: String = line.children.iter().map(|child| &*child.text).collect();
+            assert_eq!(text, line.text);
+        }
+
+        let mut test = Vec::new();
+        tokens(&cst.lines[4], &mut test);
+        assert_eq!(
+            test,
+            vec![
+                (Kind::Status, "ok "),
+                (Kind::Number, "1"),
+                (Kind::Trivia, " - "),
+                (Kind::Description, "spaced  "),
+                (Kind::Trivia, "# todo "),
+                (Kind::Text, " later"),
+                (Kind::Newline, "\n"),
+            ]
+        );
+        assert_eq!(cst.lines[2].children[0].kind, Kind::Newline);
+        assert_eq!(cst.lines[5].children[0].kind, Kind::Yaml);
+        assert_eq!(cst.lines[5].text, "  ---\n  got: 1\n  ...\n");
+        assert_eq!(cst.lines[0].newline(), "\r\n");
+    }
+
+    #[test]
+    fn test_replace() {
+        let input = "1..2\n#  keep  this\nnot ok 1\nok 2 -   second\n";
+        let mut cst = Cst::parse(input).unwrap();
+
+        cst.replace(2, "ok 1 - first").unwrap();
+        assert_eq!(
+            cst.to_string(),
+            "1..2\n#  keep  this\nok 1 - first\nok 2 -   second\n"
+        );
+        assert_eq!(
+            cst.lines[2].find(Kind::Description).map(|node| &*node.text),
+            Some("first")
+        );
+
+        assert!(cst.replace(1, "ok 3\nok 4").is_err());
+
+        // The last line needn't end in a newline, before or after.
+        let mut cst = Cst::parse("1..2\nok 1\nnot ok 2").unwrap();
+        cst.replace(2, "ok 2 - fixed").unwrap();
+        assert_eq!(cst.to_string(), "1..2\nok 1\nok 2 - fixed");
+        assert_eq!(
+            cst.lines[2].find(Kind::Test).map(|node| &*node.text),
+            Some("ok 2 - fixed")
+        );
+        cst.replace(0, "1..3\n").unwrap();
+        assert_eq!(cst.to_string(), "1..3\nok 1\nok 2 - fixed");
+
+        let error = cst.replace(3, "ok 3").unwrap_err();
+        assert!(error.to_string().contains("there is no line 3 of 3"));
+        assert_eq!(cst.to_string(), "1..3\nok 1\nok 2 - fixed");
+    }
+}
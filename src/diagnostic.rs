@@ -0,0 +1,211 @@
+use crate::document::{Line, TapDocument, TestPoint};
+
+/// What Perl's Test::More prints as comments after a failing test point:
+///
+/// ```text
+/// not ok 2 - greeting
+/// #   Failed test 'greeting'
+/// #   at t/hello.t line 12.
+/// #          got: 'hallo'
+/// #     expected: 'hello'
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Test name from `Failed test 'name'`.
+    pub name: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u64>,
+    pub got: Option<String>,
+    pub expected: Option<String>,
+    /// Every comment in the run, without the leading `#`.
+    pub lines: Vec<String>,
+}
+
+impl Diagnostic {
+    /// Parses a run of comment texts, picking out the parts Test::More uses.
+    /// Lines it doesn't recognise, like `is_deeply` structure dumps, are only
+    /// kept in `lines`.
+    pub fn parse<'t, I: IntoIterator<Item = &'t str>>(comments: I) -> Diagnostic {
+        let mut diagnostic = Diagnostic::default();
+        for comment in comments {
+            diagnostic.lines.push(comment.to_string());
+
+            let text = comment.trim();
+            if let Some(rest) = text
+                .strip_prefix("Failed test")
+                .or_else(|| text.strip_prefix("Failed (TODO) test"))
+            {
+                let rest = rest.trim_start();
+                if let (Some(start), Some(end)) = (rest.find('\''), rest.rfind('\'')) {
+                    if start < end {
+                        diagnostic.name = Some(rest[start + 1..end].to_string());
+                    }
+                }
+                diagnostic.location(rest);
+            } else if let Some(got) = text.strip_prefix("got:") {
+                diagnostic.got = Some(unquote(got.trim()).to_string());
+            } else if let Some(expected) = text.strip_prefix("expected:") {
+                diagnostic.expected = Some(unquote(expected.trim()).to_string());
+            } else {
+                diagnostic.location(text);
+            }
+        }
+        diagnostic
+    }
+
+    /// Picks up `at FILE line N.` or `in FILE at line N.` at the end of `text`.
+    fn location(&mut self, text: &str) {
+        let (before, number) = match text.trim_end_matches('.').rsplit_once(" line ") {
+            Some(location) => location,
+            None => return,
+        };
+        let number = match number.parse() {
+            Ok(number) => number,
+            Err(_) => return,
+        };
+
+        let file = match before.strip_suffix(" at") {
+            Some(before) => before
+                .strip_prefix("in ")
+                .or_else(|| before.rsplit_once(" in ").map(|(_, file)| file)),
+            None => before
+                .strip_prefix("at ")
+                .or_else(|| before.rsplit_once(" at ").map(|(_, file)| file)),
+        };
+        if let Some(file) = file {
+            self.file = Some(file.to_string());
+            self.line = Some(number);
+        }
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    }
+}
+
+/// A `not ok` test point and the diagnostics printed right after it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Failure<'d, 'a> {
+    pub test: &'d TestPoint<'a>,
+    pub diagnostic: Diagnostic,
+}
+
+impl<'a> TapDocument<'a> {
+    /// Every `not ok` test point with the run of comments that directly
+    /// follows it parsed into a `Diagnostic`. Test::More's closing
+    /// `Looks like you failed ...` comments aren't part of any failure.
+    pub fn failures(&self) -> Vec<Failure<'_, 'a>> {
+        let mut failures = Vec::new();
+        let mut lines = self.lines.iter().peekable();
+        while let Some(line) = lines.next() {
+            let test = match line {
+                Line::Test(test) if !test.ok => test,
+                _ => continue,
+            };
+
+            let mut comments = Vec::new();
+            while let Some(Line::Comment(comment)) = lines.peek() {
+                let text = comment.text.as_deref().unwrap_or("");
+                if text.trim_start().starts_with("Looks like ") {
+                    break;
+                }
+                comments.push(text);
+                lines.next();
+            }
+
+            failures.push(Failure {
+                test,
+                diagnostic: Diagnostic::parse(comments),
+            });
+        }
+        failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failures() {
+        let input = r"1..4
+ok 1 - setup
+not ok 2 - greeting
+#   Failed test 'greeting'
+#   at t/hello.t line 12.
+#          got: 'hallo'
+#     expected: 'hello'
+not ok 3
+#   Failed test at t/hello.t line 20.
+#          got: undef
+#     expected: '3'
+not ok 4 - deep
+#   Failed test 'deep'
+#   in t/lib/Deep.pm at line 7.
+#     Structures begin differing at:
+#          $got->[0] = '1'
+#     $expected->[0] = '2'
+# Looks like you failed 3 tests of 4.
+";
+        let document = TapDocument::parse(input).unwrap();
+        let failures = document.failures();
+
+        assert_eq!(failures.len(), 3);
+        assert_eq!(failures[0].test.number, Some(2));
+        assert_eq!(
+            failures[0].diagnostic,
+            Diagnostic {
+                name: Some("greeting".to_string()),
+                file: Some("t/hello.t".to_string()),
+                line: Some(12),
+                got: Some("hallo".to_string()),
+                expected: Some("hello".to_string()),
+                lines: vec![
+                    "  Failed test 'greeting'".to_string(),
+                    "  at t/hello.t line 12.".to_string(),
+                    "         got: 'hallo'".to_string(),
+                    "    expected: 'hello'".to_string(),
+                ],
+            }
+        );
+
+        let second = &failures[1].diagnostic;
+        assert_eq!(second.name, None);
+        assert_eq!(second.file.as_deref(), Some("t/hello.t"));
+        assert_eq!(second.line, Some(20));
+        assert_eq!(second.got.as_deref(), Some("undef"));
+        assert_eq!(second.expected.as_deref(), Some("3"));
+
+        let third = &failures[2].diagnostic;
+        assert_eq!(third.name.as_deref(), Some("deep"));
+        assert_eq!(third.file.as_deref(), Some("t/lib/Deep.pm"));
+        assert_eq!(third.line, Some(7));
+        assert_eq!(third.got, None);
+        assert_eq!(third.lines.len(), 5);
+    }
+
+    #[test]
+    fn test_todo_failure() {
+        let diagnostic = Diagnostic::parse(vec![
+            "  Failed (TODO) test 'later'",
+            "  at t/todo.t line 3.",
+        ]);
+
+        assert_eq!(diagnostic.name.as_deref(), Some("later"));
+        assert_eq!(diagnostic.file.as_deref(), Some("t/todo.t"));
+        assert_eq!(diagnostic.line, Some(3));
+    }
+
+    #[test]
+    fn test_no_diagnostics() {
+        let document = TapDocument::parse("not ok 1\nok 2\n1..2\n").unwrap();
+        let failures = document.failures();
+
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].diagnostic, Diagnostic::default());
+    }
+}
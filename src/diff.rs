@@ -0,0 +1,289 @@
+use std::fmt;
+
+use crate::document::{DirectiveKind, TapDocument, TestPoint};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Pass,
+    Fail,
+    Skip,
+    Todo,
+}
+
+impl Outcome {
+    pub fn of(test: &TestPoint) -> Outcome {
+        match test.directive.as_ref().map(|directive| directive.kind) {
+            Some(DirectiveKind::Skip) => Outcome::Skip,
+            Some(DirectiveKind::Todo) => Outcome::Todo,
+            None if test.ok => Outcome::Pass,
+            None => Outcome::Fail,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    NewlyFailing,
+    NewlyPassing,
+    NewlySkipped,
+    /// Marked TODO, from passing or failing.
+    NewlyTodo,
+    Added,
+    Removed,
+}
+
+/// One test point whose outcome differs between the two runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    /// Number in the later run, or in the earlier one for removed tests.
+    pub number: u64,
+    pub description: Option<String>,
+    pub before: Option<Outcome>,
+    pub after: Option<Outcome>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Diff {
+    pub changes: Vec<Change>,
+}
+
+impl Diff {
+    pub fn of(&self, kind: ChangeKind) -> impl Iterator<Item = &Change> {
+        self.changes
+            .iter()
+            .filter(move |change| change.kind == kind)
+    }
+
+    /// Whether any test started failing.
+    pub fn has_regressions(&self) -> bool {
+        self.of(ChangeKind::NewlyFailing).next().is_some()
+    }
+}
+
+struct Entry<'d, 'a> {
+    number: u64,
+    test: &'d TestPoint<'a>,
+    matched: bool,
+}
+
+fn entries<'d, 'a>(document: &'d TapDocument<'a>) -> Vec<Entry<'d, 'a>> {
+    document
+        .tests()
+        .enumerate()
+        .map(|(index, test)| Entry {
+            // Unnumbered test points count up from 1 in order.
+            number: test.number.unwrap_or(index as u64 + 1),
+            test,
+            matched: false,
+        })
+        .collect()
+}
+
+/// Compares the test points of two runs.
+///
+/// Test points are paired up by number when their descriptions agree, then by
+/// description, so tests that were renumbered because others were added or
+/// removed are still recognised. Whatever is left over was added or removed.
+pub fn diff(before: &TapDocument, after: &TapDocument) -> Diff {
+    let mut before = entries(before);
+    let mut after = entries(after);
+    let mut pairs = Vec::new();
+
+    for (a, later) in after.iter_mut().enumerate() {
+        let found = before.iter().position(|earlier| {
+            !earlier.matched
+                && earlier.number == later.number
+                && (earlier.test.description == later.test.description
+                    || earlier.test.description.is_none()
+                    || later.test.description.is_none())
+        });
+        if let Some(b) = found {
+            before[b].matched = true;
+            later.matched = true;
+            pairs.push((b, a));
+        }
+    }
+    for (a, later) in after.iter_mut().enumerate() {
+        if later.matched || later.test.description.is_none() {
+            continue;
+        }
+        let found = before.iter().position(|earlier| {
+            !earlier.matched && earlier.test.description == later.test.description
+        });
+        if let Some(b) = found {
+            before[b].matched = true;
+            later.matched = true;
+            pairs.push((b, a));
+        }
+    }
+    pairs.sort_by_key(|&(_, a)| a);
+
+    let mut changes = Vec::new();
+    for (b, a) in pairs {
+        let (was, now) = (Outcome::of(before[b].test), Outcome::of(after[a].test));
+        let kind = match now {
+            _ if was == now => continue,
+            Outcome::Fail => ChangeKind::NewlyFailing,
+            Outcome::Pass => ChangeKind::NewlyPassing,
+            Outcome::Skip => ChangeKind::NewlySkipped,
+            Outcome::Todo => ChangeKind::NewlyTodo,
+        };
+        changes.push(change(kind, &after[a], Some(was), Some(now)));
+    }
+    for entry in after.iter().filter(|entry| !entry.matched) {
+        changes.push(change(
+            ChangeKind::Added,
+            entry,
+            None,
+            Some(Outcome::of(entry.test)),
+        ));
+    }
+    for entry in before.iter().filter(|entry| !entry.matched) {
+        changes.push(change(
+            ChangeKind::Removed,
+            entry,
+            Some(Outcome::of(entry.test)),
+            None,
+        ));
+    }
+
+    Diff { changes }
+}
+
+fn change(
+    kind: ChangeKind,
+    entry: &Entry,
+    before: Option<Outcome>,
+    after: Option<Outcome>,
+) -> Change {
+    Change {
+        kind,
+        number: entry.number,
+        description: entry.test.description.as_deref().map(str::to_string),
+        before,
+        after,
+    }
+}
+
+/// One line per change, grouped by kind, e.g. `newly failing: 4 - pinged saphire`.
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let groups = [
+            (ChangeKind::NewlyFailing, "newly failing"),
+            (ChangeKind::NewlyPassing, "newly passing"),
+            (ChangeKind::NewlySkipped, "newly skipped"),
+            (ChangeKind::NewlyTodo, "newly todo"),
+            (ChangeKind::Added, "added"),
+            (ChangeKind::Removed, "removed"),
+        ];
+        for (kind, label) in groups.iter() {
+            for change in self.of(*kind) {
+                write!(f, "{}: {}", label, change.number)?;
+                if let Some(description) = &change.description {
+                    write!(f, " - {}", description)?;
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(input: &str) -> TapDocument<'_> {
+        TapDocument::parse(input).unwrap()
+    }
+
+    #[test]
+    fn test_diff_by_number() {
+        let before = run("1..5\nok 1 - a\nnot ok 2 - b\nok 3 - c\nok 4 - d\nok 5 - e\n");
+        let after = run("1..5\nnot ok 1 - a\nok 2 - b\nok 3 - c # SKIP later\nok 4 - d\nnot ok 5 - e # TODO later\n");
+        let diff = diff(&before, &after);
+
+        assert_eq!(
+            diff.changes,
+            vec![
+                Change {
+                    kind: ChangeKind::NewlyFailing,
+                    number: 1,
+                    description: Some("a".to_string()),
+                    before: Some(Outcome::Pass),
+                    after: Some(Outcome::Fail),
+                },
+                Change {
+                    kind: ChangeKind::NewlyPassing,
+                    number: 2,
+                    description: Some("b".to_string()),
+                    before: Some(Outcome::Fail),
+                    after: Some(Outcome::Pass),
+                },
+                Change {
+                    kind: ChangeKind::NewlySkipped,
+                    number: 3,
+                    description: Some("c".to_string()),
+                    before: Some(Outcome::Pass),
+                    after: Some(Outcome::Skip),
+                },
+                Change {
+                    kind: ChangeKind::NewlyTodo,
+                    number: 5,
+                    description: Some("e".to_string()),
+                    before: Some(Outcome::Pass),
+                    after: Some(Outcome::Todo),
+                },
+            ]
+        );
+        assert!(diff.has_regressions());
+        assert!(diff.to_string().ends_with("newly todo: 5 - e\n"));
+
+        // Only a test starting to fail is a regression.
+        let todo = super::diff(
+            &before,
+            &run("1..5\nok 1 - a\nnot ok 2 - b # TODO\nok 3 - c\nok 4 - d\nok 5 - e\n"),
+        );
+        assert_eq!(todo.of(ChangeKind::NewlyTodo).count(), 1);
+        assert!(!todo.has_regressions());
+    }
+
+    #[test]
+    fn test_diff_by_description() {
+        let before = run("1..3\nok 1 - a\nok 2 - b\nnot ok 3 - c\n");
+        let after = run("1..4\nok 1 - new\nok 2 - a\nnot ok 3 - b\nok 4 - c\n");
+        let diff = diff(&before, &after);
+
+        assert_eq!(
+            diff.of(ChangeKind::NewlyFailing)
+                .map(|change| change.number)
+                .collect::<Vec<_>>(),
+            vec![3]
+        );
+        assert_eq!(
+            diff.of(ChangeKind::NewlyPassing)
+                .map(|change| change.number)
+                .collect::<Vec<_>>(),
+            vec![4]
+        );
+        assert_eq!(
+            diff.of(ChangeKind::Added)
+                .map(|change| change.description.as_deref())
+                .collect::<Vec<_>>(),
+            vec![Some("new")]
+        );
+        assert_eq!(diff.of(ChangeKind::Removed).count(), 0);
+    }
+
+    #[test]
+    fn test_diff_removed_and_unnumbered() {
+        let before = run("ok\nok\nok - gone\n1..3\n");
+        let after = run("ok\nnot ok\n1..2\n");
+        let diff = diff(&before, &after);
+
+        assert_eq!(diff.to_string(), "newly failing: 2\nremoved: 3 - gone\n");
+        assert!(diff.has_regressions());
+        assert_eq!(super::diff(&after, &after), Diff::default());
+    }
+}
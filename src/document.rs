@@ -0,0 +1,826 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::time::Duration;
+
+use pest::error::{Error, ErrorVariant};
+use pest::iterators::Pair;
+use pest::Parser;
+
+use crate::tap_parser::{Rule, TapParser};
+
+/// Byte range of a node in the parsed input, plus the 1-based line it starts on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+}
+
+/// `TAP version N`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Version {
+    pub version: u64,
+    pub span: Span,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DirectiveKind {
+    Todo,
+    Skip,
+}
+
+/// `# TODO reason` or `# SKIP reason`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Directive<'a> {
+    pub kind: DirectiveKind,
+    pub reason: Option<Cow<'a, str>>,
+    pub span: Span,
+}
+
+/// `1..N`, optionally followed by a skip directive when N is 0.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Plan<'a> {
+    pub count: u64,
+    pub directive: Option<Directive<'a>>,
+    pub span: Span,
+}
+
+/// `ok`/`not ok` line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestPoint<'a> {
+    pub ok: bool,
+    pub number: Option<u64>,
+    /// With any `\#` read back as `#`.
+    pub description: Option<Cow<'a, str>>,
+    pub directive: Option<Directive<'a>>,
+    /// How long the test took, when known. TAP itself doesn't say; see
+    /// `StreamParser::timed`.
+    pub duration: Option<Duration>,
+    /// node-tap's `# time=` after the test point as written, e.g. `12.3ms`.
+    pub time: Option<Cow<'a, str>>,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Comment<'a> {
+    pub text: Option<Cow<'a, str>>,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bailout<'a> {
+    pub reason: Option<Cow<'a, str>>,
+    pub span: Span,
+}
+
+/// Indented `---` ... `...` diagnostics block following a test point, kept as
+/// the raw text between the markers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Yaml<'a> {
+    pub text: Cow<'a, str>,
+    pub span: Span,
+}
+
+/// Any line the grammar doesn't recognise.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unknown<'a> {
+    pub text: Cow<'a, str>,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Line<'a> {
+    Version(Version),
+    Plan(Plan<'a>),
+    Test(TestPoint<'a>),
+    Comment(Comment<'a>),
+    Bailout(Bailout<'a>),
+    Yaml(Yaml<'a>),
+    Unknown(Unknown<'a>),
+}
+
+/// Typed view of a TAP stream, in input order.
+///
+/// Text borrows from the input where possible; use `into_owned` when the
+/// document has to outlive it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TapDocument<'a> {
+    pub lines: Vec<Line<'a>>,
+}
+
+impl<'a> TapDocument<'a> {
+    pub fn parse(input: &'a str) -> Result<TapDocument<'a>, Error<Rule>> {
+        let tap = TapParser::parse(Rule::tap, input)?
+            .next()
+            .expect("tap rule always produces a pair");
+
+        let mut builder = Builder::new(input, 0, 1);
+        for pair in tap.into_inner() {
+            builder.push(pair)?;
+        }
+        builder.document.attach_durations();
+
+        Ok(builder.document)
+    }
+
+    /// Parses raw output, which test programs don't always print as valid
+    /// UTF-8. Invalid sequences become U+FFFD, which leaves the structure of
+    /// each line intact: garbage lines come back as `Line::Unknown` and
+    /// Latin-1 descriptions come through lossily. Valid input is borrowed.
+    pub fn parse_bytes(input: &'a [u8]) -> Result<TapDocument<'a>, Error<Rule>> {
+        match String::from_utf8_lossy(input) {
+            Cow::Borrowed(text) => TapDocument::parse(text),
+            Cow::Owned(text) => TapDocument::parse(&text).map(TapDocument::into_owned),
+        }
+    }
+
+    pub fn version(&self) -> Option<&Version> {
+        match self.lines.first() {
+            Some(Line::Version(version)) => Some(version),
+            _ => None,
+        }
+    }
+
+    pub fn plan(&self) -> Option<&Plan<'a>> {
+        self.lines.iter().find_map(|line| match line {
+            Line::Plan(plan) => Some(plan),
+            _ => None,
+        })
+    }
+
+    pub fn tests(&self) -> impl Iterator<Item = &TestPoint<'a>> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Test(test) => Some(test),
+            _ => None,
+        })
+    }
+
+    /// The whole run's time, from the `# time=` comment node-tap closes a
+    /// stream with.
+    pub fn duration(&self) -> Option<Duration> {
+        self.lines
+            .iter()
+            .rev()
+            .take_while(|line| !matches!(line, Line::Test(_)))
+            .find_map(|line| match line {
+                Line::Comment(comment) => comment.text.as_deref().and_then(time_comment),
+                _ => None,
+            })
+    }
+
+    /// Gives test points the `duration_ms` of a YAML block right after them,
+    /// overriding any time measured while parsing.
+    pub(crate) fn attach_durations(&mut self) {
+        for index in 1..self.lines.len() {
+            let duration = match &self.lines[index] {
+                Line::Yaml(yaml) => yaml.duration(),
+                _ => None,
+            };
+            if let (Some(duration), Line::Test(test)) = (duration, &mut self.lines[index - 1]) {
+                test.duration = Some(duration);
+            }
+        }
+    }
+
+    pub fn into_owned(self) -> TapDocument<'static> {
+        TapDocument {
+            lines: self.lines.into_iter().map(Line::into_owned).collect(),
+        }
+    }
+}
+
+impl<'a> Line<'a> {
+    pub fn span(&self) -> Span {
+        match self {
+            Line::Version(version) => version.span,
+            Line::Plan(plan) => plan.span,
+            Line::Test(test) => test.span,
+            Line::Comment(comment) => comment.span,
+            Line::Bailout(bailout) => bailout.span,
+            Line::Yaml(yaml) => yaml.span,
+            Line::Unknown(unknown) => unknown.span,
+        }
+    }
+
+    pub fn into_owned(self) -> Line<'static> {
+        match self {
+            Line::Version(version) => Line::Version(version),
+            Line::Plan(plan) => Line::Plan(Plan {
+                count: plan.count,
+                directive: plan.directive.map(Directive::into_owned),
+                span: plan.span,
+            }),
+            Line::Test(test) => Line::Test(test.into_owned()),
+            Line::Comment(comment) => Line::Comment(Comment {
+                text: comment.text.map(owned),
+                span: comment.span,
+            }),
+            Line::Bailout(bailout) => Line::Bailout(Bailout {
+                reason: bailout.reason.map(owned),
+                span: bailout.span,
+            }),
+            Line::Yaml(yaml) => Line::Yaml(Yaml {
+                text: owned(yaml.text),
+                span: yaml.span,
+            }),
+            Line::Unknown(unknown) => Line::Unknown(Unknown {
+                text: owned(unknown.text),
+                span: unknown.span,
+            }),
+        }
+    }
+}
+
+impl<'a> TestPoint<'a> {
+    pub fn into_owned(self) -> TestPoint<'static> {
+        TestPoint {
+            ok: self.ok,
+            number: self.number,
+            description: self.description.map(owned),
+            directive: self.directive.map(Directive::into_owned),
+            duration: self.duration,
+            time: self.time.map(owned),
+            span: self.span,
+        }
+    }
+}
+
+impl<'a> Yaml<'a> {
+    /// The block's top-level `duration_ms`, as node-tap writes for subtests.
+    pub fn duration(&self) -> Option<Duration> {
+        self.text
+            .lines()
+            .find_map(|line| line.strip_prefix("  duration_ms:"))
+            .and_then(|value| milliseconds(value.trim()))
+    }
+}
+
+impl<'a> Directive<'a> {
+    pub fn into_owned(self) -> Directive<'static> {
+        Directive {
+            kind: self.kind,
+            reason: self.reason.map(owned),
+            span: self.span,
+        }
+    }
+}
+
+/// Writes the document back out as TAP, a line at a time.
+impl<'a> fmt::Display for TapDocument<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the line back out as TAP, without the trailing newline.
+impl<'a> fmt::Display for Line<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Version(version) => write!(f, "TAP version {}", version.version),
+            Line::Plan(plan) => {
+                write!(f, "1..{}", plan.count)?;
+                if let Some(directive) = &plan.directive {
+                    write!(f, " {}", directive)?;
+                }
+                Ok(())
+            }
+            Line::Test(test) => test.fmt(f),
+            Line::Comment(comment) => match &comment.text {
+                Some(text) => write!(f, "# {}", text),
+                None => write!(f, "#"),
+            },
+            Line::Bailout(bailout) => match &bailout.reason {
+                Some(reason) => write!(f, "Bail out! {}", reason),
+                None => write!(f, "Bail out!"),
+            },
+            Line::Yaml(yaml) => write!(f, "  ---\n{}  ...", yaml.text),
+            Line::Unknown(unknown) => f.write_str(&unknown.text),
+        }
+    }
+}
+
+impl<'a> fmt::Display for TestPoint<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.ok { "ok" } else { "not ok" })?;
+        if let Some(number) = self.number {
+            write!(f, " {}", number)?;
+        }
+        if let Some(description) = &self.description {
+            write!(f, " - {}", description.replace('#', "\\#"))?;
+        }
+        // A `# time=` after a directive would parse back as part of its
+        // reason, so it's only written without one.
+        match (&self.directive, &self.time) {
+            (Some(directive), _) => write!(f, " {}", directive),
+            (None, Some(time)) => write!(f, " # time={}", time),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl<'a> fmt::Display for Directive<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            DirectiveKind::Todo => "TODO",
+            DirectiveKind::Skip => "SKIP",
+        };
+        match &self.reason {
+            Some(reason) => write!(f, "# {} {}", kind, reason),
+            None => write!(f, "# {}", kind),
+        }
+    }
+}
+
+/// Parses a single line, or a complete YAML block, of a stream where it starts
+/// at byte `offset` on line number `line`. Blank lines produce nothing, as they
+/// do in a whole document.
+pub(crate) fn parse_stream_line(
+    input: &str,
+    offset: usize,
+    line: usize,
+) -> Result<Vec<Line<'_>>, Error<Rule>> {
+    let mut builder = Builder::new(input, offset, line);
+    for pair in TapParser::parse(Rule::stream_line, input)? {
+        builder.push(pair)?;
+    }
+
+    Ok(builder.document.lines)
+}
+
+fn milliseconds(value: &str) -> Option<Duration> {
+    let milliseconds: f64 = value.parse().ok()?;
+    Duration::try_from_secs_f64(milliseconds / 1000.0).ok()
+}
+
+/// `time=12.3ms` or `time=2s`, as in node-tap's comments.
+fn time_comment(text: &str) -> Option<Duration> {
+    let value = text.trim().strip_prefix("time=")?;
+    match value.strip_suffix("ms") {
+        Some(value) => milliseconds(value),
+        None => milliseconds(value.strip_suffix('s')?)?.checked_mul(1000),
+    }
+}
+
+fn owned(text: Cow<str>) -> Cow<'static, str> {
+    Cow::Owned(text.into_owned())
+}
+
+/// Tracks line numbers as spans are visited in input order, so finding each
+/// node's line doesn't rescan the input from the start.
+struct LineCounter<'a> {
+    input: &'a str,
+    base: usize,
+    offset: usize,
+    line: usize,
+}
+
+impl<'a> LineCounter<'a> {
+    fn new(input: &'a str, base: usize, line: usize) -> Self {
+        LineCounter {
+            input,
+            base,
+            offset: 0,
+            line,
+        }
+    }
+
+    fn span(&mut self, span: pest::Span) -> Span {
+        let start = span.start();
+        if start >= self.offset {
+            self.line += self.input[self.offset..start].matches('\n').count();
+        } else {
+            self.line -= self.input[start..self.offset].matches('\n').count();
+        }
+        self.offset = start;
+
+        Span {
+            start: self.base + start,
+            end: self.base + span.end(),
+            line: self.line,
+        }
+    }
+}
+
+struct Builder<'a> {
+    lines: LineCounter<'a>,
+    document: TapDocument<'a>,
+}
+
+impl<'a> Builder<'a> {
+    fn new(input: &'a str, offset: usize, line: usize) -> Self {
+        Builder {
+            lines: LineCounter::new(input, offset, line),
+            document: TapDocument::default(),
+        }
+    }
+
+    fn push(&mut self, pair: Pair<'a, Rule>) -> Result<(), Error<Rule>> {
+        match pair.as_rule() {
+            Rule::version => {
+                let mut span = self.lines.span(pair.as_span());
+                let number = pair.into_inner().next().expect("version has a number");
+                // Leave out the NEWLINE the version rule consumes.
+                span.end = self.lines.base + number.as_span().end();
+                self.document.lines.push(Line::Version(Version {
+                    version: integer(&number)?,
+                    span,
+                }));
+            }
+            Rule::plan => {
+                let span = self.lines.span(pair.as_span());
+                let count = pair.into_inner().next().expect("plan has a count");
+                self.document.lines.push(Line::Plan(Plan {
+                    count: integer(&count)?,
+                    directive: None,
+                    span,
+                }));
+            }
+            // A leading plan's skip directive is a sibling of the plan pair.
+            Rule::skip_directive => {
+                let directive = self.directive(pair);
+                if let Some(Line::Plan(plan)) = self.document.lines.last_mut() {
+                    plan.directive = Some(directive);
+                }
+            }
+            Rule::test => {
+                let test = self.test(pair)?;
+                self.document.lines.push(Line::Test(test));
+            }
+            Rule::comment => {
+                let span = self.lines.span(pair.as_span());
+                self.document.lines.push(Line::Comment(Comment {
+                    text: text_output(pair),
+                    span,
+                }));
+            }
+            Rule::bailout => {
+                let span = self.lines.span(pair.as_span());
+                self.document.lines.push(Line::Bailout(Bailout {
+                    reason: text_output(pair),
+                    span,
+                }));
+            }
+            Rule::yaml => {
+                let span = self.lines.span(pair.as_span());
+                let text = pair.into_inner().next().map_or("", |text| text.as_str());
+                self.document.lines.push(Line::Yaml(Yaml {
+                    text: Cow::Borrowed(text),
+                    span,
+                }));
+            }
+            Rule::unknown | Rule::noplan_unknown => {
+                let span = self.lines.span(pair.as_span());
+                self.document.lines.push(Line::Unknown(Unknown {
+                    text: Cow::Borrowed(pair.as_str()),
+                    span,
+                }));
+            }
+            Rule::EOI => {}
+            rule => unreachable!("unexpected {:?} in tap", rule),
+        }
+
+        Ok(())
+    }
+
+    fn test(&mut self, pair: Pair<'a, Rule>) -> Result<TestPoint<'a>, Error<Rule>> {
+        let mut test = TestPoint {
+            ok: true,
+            number: None,
+            description: None,
+            directive: None,
+            duration: None,
+            time: None,
+            span: self.lines.span(pair.as_span()),
+        };
+
+        let mut description_start = None;
+        for inner in pair.into_inner() {
+            match inner.as_rule() {
+                Rule::status => test.ok = !inner.as_str().starts_with("not"),
+                Rule::positiveInteger => test.number = Some(integer(&inner)?),
+                Rule::desc_text => {
+                    description_start = Some(inner.as_span().start());
+                    let description = inner.as_str().trim_end();
+                    if !description.is_empty() {
+                        test.description = Some(unescape(description));
+                    }
+                }
+                Rule::todo_directive | Rule::skip_directive => {
+                    test.directive = Some(self.directive(inner))
+                }
+                Rule::time => match time(&inner) {
+                    Some(duration) => {
+                        test.duration = Some(duration);
+                        let (_, value) = inner.as_str().split_once('=').expect("time has an =");
+                        test.time = Some(Cow::Borrowed(value));
+                    }
+                    // Too long to be a time, so it's just more description.
+                    None => {
+                        let span = inner.as_span();
+                        let start = description_start.unwrap_or(span.start());
+                        test.description = Some(unescape(&self.lines.input[start..span.end()]));
+                    }
+                },
+                rule => unreachable!("unexpected {:?} in test", rule),
+            }
+        }
+
+        Ok(test)
+    }
+
+    fn directive(&mut self, pair: Pair<'a, Rule>) -> Directive<'a> {
+        let kind = match pair.as_rule() {
+            Rule::todo_directive => DirectiveKind::Todo,
+            _ => DirectiveKind::Skip,
+        };
+        let span = self.lines.span(pair.as_span());
+
+        Directive {
+            kind,
+            reason: text_output(pair),
+            span,
+        }
+    }
+}
+
+/// A description with its `\#` escapes read back as `#`.
+fn unescape(description: &str) -> Cow<'_, str> {
+    if description.contains("\\#") {
+        Cow::Owned(description.replace("\\#", "#"))
+    } else {
+        Cow::Borrowed(description)
+    }
+}
+
+fn text_output(pair: Pair<Rule>) -> Option<Cow<str>> {
+    pair.into_inner()
+        .find(|inner| inner.as_rule() == Rule::text_output)
+        .map(|text| Cow::Borrowed(text.as_str()))
+}
+
+fn time(pair: &Pair<Rule>) -> Option<Duration> {
+    time_comment(pair.as_str().trim_start_matches(&['#', ' '][..]))
+}
+
+fn integer(pair: &Pair<Rule>) -> Result<u64, Error<Rule>> {
+    pair.as_str().parse().map_err(|_| {
+        Error::new_from_span(
+            ErrorVariant::CustomError {
+                message: format!("{} is out of range", pair.as_str()),
+            },
+            pair.as_span(),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_leading_plan() {
+        let input = "TAP version 13\n1..3\nok 1 - first\n# a comment\nnot ok 2 - second # TODO later\nok 3 # SKIP no network\n";
+        let document = TapDocument::parse(input).unwrap();
+
+        assert_eq!(
+            document.version(),
+            Some(&Version {
+                version: 13,
+                span: Span {
+                    start: 0,
+                    end: 14,
+                    line: 1
+                }
+            })
+        );
+        assert_eq!(
+            document.plan(),
+            Some(&Plan {
+                count: 3,
+                directive: None,
+                span: Span {
+                    start: 15,
+                    end: 19,
+                    line: 2
+                }
+            })
+        );
+        assert_eq!(
+            document.lines[3],
+            Line::Comment(Comment {
+                text: Some(Cow::Borrowed("a comment")),
+                span: Span {
+                    start: 33,
+                    end: 44,
+                    line: 4
+                }
+            })
+        );
+
+        let tests: Vec<_> = document.tests().collect();
+        assert_eq!(tests.len(), 3);
+        assert_eq!(
+            tests[0],
+            &TestPoint {
+                ok: true,
+                number: Some(1),
+                description: Some(Cow::Borrowed("first")),
+                directive: None,
+                duration: None,
+                time: None,
+                span: Span {
+                    start: 20,
+                    end: 32,
+                    line: 3
+                }
+            }
+        );
+        assert!(!tests[1].ok);
+        assert_eq!(tests[1].description.as_deref(), Some("second"));
+        assert_eq!(
+            tests[1].directive,
+            Some(Directive {
+                kind: DirectiveKind::Todo,
+                reason: Some(Cow::Borrowed("later")),
+                span: Span {
+                    start: 63,
+                    end: 75,
+                    line: 5
+                }
+            })
+        );
+        assert_eq!(tests[2].description, None);
+        assert_eq!(
+            tests[2].directive.as_ref().unwrap().kind,
+            DirectiveKind::Skip
+        );
+        assert_eq!(tests[2].span.line, 6);
+    }
+
+    #[test]
+    fn test_parse_trailing_plan() {
+        let input = "ok 1\nnot ok 2\nBail out! no database\n1..2\n";
+        let document = TapDocument::parse(input).unwrap();
+
+        assert_eq!(document.version(), None);
+        assert_eq!(document.lines.len(), 4);
+        assert_eq!(
+            document.lines[2],
+            Line::Bailout(Bailout {
+                reason: Some(Cow::Borrowed("no database")),
+                span: Span {
+                    start: 14,
+                    end: 35,
+                    line: 3
+                }
+            })
+        );
+        assert_eq!(document.plan().unwrap().span.line, 4);
+    }
+
+    #[test]
+    fn test_parse_skip_all_plan() {
+        let document = TapDocument::parse("1..0 # skip no translator\n").unwrap();
+        let plan = document.plan().unwrap();
+
+        assert_eq!(plan.count, 0);
+        assert_eq!(plan.directive.as_ref().unwrap().kind, DirectiveKind::Skip);
+        assert_eq!(
+            plan.directive.as_ref().unwrap().reason.as_deref(),
+            Some("no translator")
+        );
+    }
+
+    #[test]
+    fn test_parse_number_out_of_range() {
+        assert!(TapDocument::parse("1..1\nok 99999999999999999999999\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_bytes() {
+        let input = b"1..3\nok 1 - caf\xe9\n\xff\xfe\x00garbage\nnot ok 2\nok 3\n";
+        let document = TapDocument::parse_bytes(input).unwrap();
+        let lines: Vec<_> = document.lines.iter().map(ToString::to_string).collect();
+
+        assert_eq!(
+            lines,
+            [
+                "1..3",
+                "ok 1 - caf\u{fffd}",
+                "\u{fffd}\u{fffd}\u{0}garbage",
+                "not ok 2",
+                "ok 3"
+            ]
+        );
+        assert!(matches!(document.lines[2], Line::Unknown(_)));
+        let valid = TapDocument::parse_bytes(b"ok 1 - x\n").unwrap();
+        let Line::Test(test) = &valid.lines[0] else {
+            panic!("not a test point");
+        };
+        assert!(matches!(test.description, Some(Cow::Borrowed("x"))));
+    }
+
+    #[test]
+    fn test_display() {
+        let input = "TAP version 13\n1..4\nok 1 - first\n# a comment\n#\nnot ok 2 - second # TODO later\n  ---\n  got: 1\n  ...\nok 3 # SKIP\nok\nBail out! stop\nwhat is this\n";
+        let document = TapDocument::parse(input).unwrap();
+        let lines: Vec<_> = document.lines.iter().map(|line| line.to_string()).collect();
+
+        assert_eq!(lines.join("\n") + "\n", input);
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let input = "ok 1 - quick # time=2s\nok 2 # TODO # time=1ms\nnot ok 3 # SKIP slow\n";
+        let document = TapDocument::parse(input).unwrap();
+        assert_eq!(document.to_string(), input);
+
+        let mut timed = document.tests().nth(2).unwrap().clone();
+        timed.time = Some(Cow::Borrowed("5ms"));
+        let text = timed.to_string();
+        assert_eq!(text, "not ok 3 # SKIP slow");
+        let reparsed = TapDocument::parse(&text).unwrap();
+        assert_eq!(reparsed.to_string(), format!("{}\n", text));
+        let directive = reparsed.tests().next().unwrap().directive.clone().unwrap();
+        assert_eq!(directive.reason.as_deref(), Some("slow"));
+    }
+
+    #[test]
+    fn test_into_owned() {
+        let input = String::from("1..1\nok 1 - borrowed\nwhat is this\n");
+        let document = TapDocument::parse(&input).unwrap();
+        let owned = document.clone().into_owned();
+        drop(input);
+
+        assert_eq!(owned.lines.len(), 3);
+        assert_eq!(
+            owned.tests().next().unwrap().description.as_deref(),
+            Some("borrowed")
+        );
+        assert!(matches!(
+            &owned.lines[2],
+            Line::Unknown(Unknown {
+                text: Cow::Owned(text),
+                span: Span { line: 3, .. }
+            }) if text == "what is this"
+        ));
+    }
+
+    #[test]
+    fn test_durations() {
+        let input = "ok 1 - quick # time=2s
+ok 2 - yaml
+  ---
+  duration_ms: 12.5
+  ...
+ok 3 - nested
+  ---
+  at:
+    duration_ms: 1
+  ...
+ok 4 # TODO # time=1ms
+1..4
+# time=2.5s
+";
+        let document = TapDocument::parse(input).unwrap();
+        let tests: Vec<_> = document.tests().collect();
+
+        assert_eq!(tests[0].description.as_deref(), Some("quick"));
+        assert_eq!(tests[0].duration, Some(Duration::from_secs(2)));
+        assert_eq!(tests[1].duration, Some(Duration::from_micros(12500)));
+        assert_eq!(tests[2].duration, None);
+        assert_eq!(tests[3].duration, None);
+        assert_eq!(document.duration(), Some(Duration::from_millis(2500)));
+
+        assert_eq!(tests[0].to_string(), "ok 1 - quick # time=2s");
+        let too_long = format!("ok 1 # time={}ms\n", "9".repeat(400));
+        let document = TapDocument::parse(&too_long).unwrap();
+        let test = document.tests().next().unwrap();
+        assert_eq!(
+            test.description.as_deref(),
+            Some(too_long.trim_end()[5..].as_ref())
+        );
+        assert_eq!(test.duration, None);
+        let input = "ok 1 - slow # time=99999999999999999999s\n";
+        let document = TapDocument::parse(input).unwrap();
+        let test = document.tests().next().unwrap();
+        assert_eq!(
+            test.description.as_deref(),
+            Some("slow # time=99999999999999999999s")
+        );
+        assert_eq!((test.duration, test.time.as_deref()), (None, None));
+        assert_eq!(
+            test.to_string(),
+            "ok 1 - slow \\# time=99999999999999999999s"
+        );
+        let displayed = test.to_string();
+        let reparsed = TapDocument::parse(&displayed).unwrap();
+        assert_eq!(
+            reparsed.tests().next().unwrap().description,
+            test.description
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use std::borrow::Cow;
+use std::io::{self, BufRead};
+
+use regex::Regex;
+
+use crate::document::{parse_stream_line, Line};
+
+/// Pulls the TAP out of a build log that interleaves it with other output,
+/// colors it and prefixes every line with something like a timestamp,
+/// `[worker-3] ` or `container_1 | `.
+///
+/// Each line has its ANSI escapes and then the configured prefix removed
+/// before it's matched as TAP. What doesn't match is dropped, except for
+/// indented lines inside a stream, which are kept for subtests and YAML
+/// blocks. A log can hold several streams: a new one starts at a version
+/// line, at a plan when the current stream already has one, and at a test
+/// point once the current stream is complete.
+///
+/// ```
+/// use tap_pest::extract::Extractor;
+///
+/// let log = "[worker-1] \x1b[32mok 1 - builds\x1b[0m\n[worker-1] 1..1\nnpm WARN deprecated\n";
+/// let streams = Extractor::new()
+///     .prefix(r"\[worker-\d+\] ")
+///     .unwrap()
+///     .extract(log.as_bytes())
+///     .unwrap();
+/// assert_eq!(streams, vec!["ok 1 - builds\n1..1\n"]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Extractor {
+    ansi: Regex,
+    prefix: Option<Regex>,
+}
+
+impl Default for Extractor {
+    fn default() -> Self {
+        Extractor::new()
+    }
+}
+
+impl Extractor {
+    pub fn new() -> Self {
+        Extractor {
+            // CSI sequences like colors, and OSC sequences like hyperlinks.
+            ansi: Regex::new(r"\x1b\[[0-?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(\x07|\x1b\\)")
+                .expect("ANSI pattern is valid"),
+            prefix: None,
+        }
+    }
+
+    /// Strip text matching `pattern` from the start of each line.
+    pub fn prefix(mut self, pattern: &str) -> Result<Self, regex::Error> {
+        self.prefix = Some(Regex::new(&format!("^(?:{})", pattern))?);
+        Ok(self)
+    }
+
+    /// `line` without ANSI escapes or the prefix.
+    pub fn clean<'l>(&self, line: &'l str) -> Cow<'l, str> {
+        let line = self.ansi.replace_all(line, "");
+        match &self.prefix {
+            Some(prefix) => match prefix.find(&line) {
+                Some(found) => Cow::Owned(line[found.end()..].to_string()),
+                None => line,
+            },
+            None => line,
+        }
+    }
+
+    /// The TAP streams found in the log, in order, each ready for
+    /// `TapDocument::parse` or `TapDocument::from_reader`.
+    pub fn extract<R: BufRead>(&self, mut reader: R) -> io::Result<Vec<String>> {
+        let mut streams = Vec::new();
+        let mut current: Option<Stream> = None;
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                break;
+            }
+            let raw = String::from_utf8_lossy(&buf);
+            let mut text = self
+                .clean(raw.trim_end_matches(&['\r', '\n'][..]))
+                .into_owned();
+            text.push('\n');
+
+            let line = match parse_stream_line(&text, 0, 1) {
+                Ok(lines) => lines.into_iter().next(),
+                Err(_) => continue,
+            };
+            let starts_stream = match (&line, &current) {
+                (None, _) => continue,
+                (Some(Line::Unknown(_)), Some(_)) if text.starts_with(char::is_whitespace) => false,
+                (Some(Line::Unknown(_)), _) => continue,
+                (Some(Line::Version(_)), _) | (Some(_), None) => true,
+                (Some(Line::Plan(_)), Some(stream)) => stream.plan.is_some(),
+                (Some(Line::Test(_)), Some(stream)) => stream.is_complete(),
+                (Some(_), Some(_)) => false,
+            };
+            if starts_stream {
+                streams.extend(current.take().and_then(Stream::finish));
+                current = Some(Stream::default());
+            }
+
+            let stream = current.as_mut().expect("a stream was started");
+            match &line {
+                Some(Line::Plan(plan)) => {
+                    stream.plan = Some(plan.count);
+                    stream.trailing_plan = stream.tests > 0;
+                }
+                Some(Line::Test(_)) => stream.tests += 1,
+                Some(Line::Bailout(_)) => stream.bailed_out = true,
+                _ => {}
+            }
+            stream.text.push_str(&text);
+        }
+        streams.extend(current.and_then(Stream::finish));
+
+        Ok(streams)
+    }
+}
+
+#[derive(Default)]
+struct Stream {
+    text: String,
+    plan: Option<u64>,
+    trailing_plan: bool,
+    tests: u64,
+    bailed_out: bool,
+}
+
+impl Stream {
+    /// Further test points must belong to another stream.
+    fn is_complete(&self) -> bool {
+        self.trailing_plan || self.bailed_out || self.plan.is_some_and(|plan| self.tests >= plan)
+    }
+
+    /// The stream's text, unless it's only comments.
+    fn finish(self) -> Option<String> {
+        if self.plan.is_some() || self.tests > 0 || self.text.starts_with("TAP version") {
+            Some(self.text)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::TapDocument;
+
+    #[test]
+    fn test_extract() {
+        let log = "\
+2024-05-01T10:00:00Z container_1 | Pulling image...
+2024-05-01T10:00:01Z container_1 | # building fixtures
+2024-05-01T10:00:02Z container_1 | TAP version 13
+2024-05-01T10:00:02Z container_1 | 1..2
+2024-05-01T10:00:03Z container_1 | \x1b[32mok 1 - connects\x1b[0m
+2024-05-01T10:00:03Z container_1 | \x1b[31mnot ok 2 - queries\x1b[0m
+2024-05-01T10:00:03Z container_1 |   ---
+2024-05-01T10:00:03Z container_1 |   message: timeout
+2024-05-01T10:00:03Z container_1 |   ...
+2024-05-01T10:00:04Z container_1 | Stopping container_1 ... done
+2024-05-01T10:00:05Z container_2 | ok 1 - renders\r
+2024-05-01T10:00:05Z container_2 | ok 2 - caches\r
+2024-05-01T10:00:05Z container_2 | 1..2\r
+2024-05-01T10:00:05Z container_2 | # all passed\r
+2024-05-01T10:00:06Z container_1 | ok 1 - retried
+2024-05-01T10:00:06Z container_1 | 1..1
+Done in 6s.
+";
+        let streams = Extractor::new()
+            .prefix(r"\S+Z \w+ \| ")
+            .unwrap()
+            .extract(log.as_bytes())
+            .unwrap();
+
+        assert_eq!(
+            streams,
+            vec![
+                "TAP version 13\n1..2\nok 1 - connects\nnot ok 2 - queries\n  ---\n  message: timeout\n  ...\n",
+                "ok 1 - renders\nok 2 - caches\n1..2\n# all passed\n",
+                "ok 1 - retried\n1..1\n",
+            ]
+        );
+        for stream in &streams {
+            assert!(TapDocument::parse(stream).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_extract_leading_plans() {
+        let log = "1..1\nok 1\nmake: entering directory\n1..2\nok 1\n# slow\nok 2\nok 3\n";
+        let streams = Extractor::new().extract(log.as_bytes()).unwrap();
+
+        assert_eq!(
+            streams,
+            vec!["1..1\nok 1\n", "1..2\nok 1\n# slow\nok 2\n", "ok 3\n"]
+        );
+    }
+
+    #[test]
+    fn test_clean() {
+        let extractor = Extractor::new().prefix(r"\[worker-\d+\] ").unwrap();
+
+        assert_eq!(
+            extractor
+                .clean("[worker-3] \x1b[1;32mok\x1b[0m 1 \x1b]8;;http://x\x07link\x1b]8;;\x07"),
+            "ok 1 link"
+        );
+        assert_eq!(extractor.clean("[other] ok 1"), "[other] ok 1");
+        assert!(Extractor::new().prefix("(").is_err());
+    }
+}
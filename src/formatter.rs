@@ -0,0 +1,429 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::document::{DirectiveKind, Line};
+use crate::harness::FileResult;
+
+/// Receives progress from the `Harness`, mirroring Perl's `TAP::Formatter::*`.
+///
+/// `file_start`, then `line` for each line as the program prints it and
+/// `stderr` for each line it writes to stderr, then `file_end` for every file,
+/// and `finish` once at the end of the run. When a failing file is retried,
+/// `retry` comes between the attempts. All methods do nothing by default.
+pub trait Formatter {
+    fn file_start(&mut self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn line(&mut self, _path: &Path, _line: &Line) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// `text` keeps its line ending.
+    fn stderr(&mut self, _path: &Path, _text: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// `attempt` failed and the file is about to run again.
+    fn retry(&mut self, _attempt: &FileResult) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn file_end(&mut self, _result: &FileResult) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self, _results: &[FileResult]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// `ok`, or why the file failed, e.g. `Failed 1/3 subtests, exit status: 2`.
+/// A file that only passed on a retry is a `flaky pass`.
+pub fn verdict(result: &FileResult) -> String {
+    let summary = &result.summary;
+    let attempts = result.attempts.len() + 1;
+    if result.is_flaky() {
+        return format!("flaky pass on attempt {}", attempts);
+    }
+    if result.is_ok() {
+        return match &summary.skip_all {
+            Some(reason) => format!("skipped: {}", reason),
+            None => "ok".to_string(),
+        };
+    }
+
+    let mut reasons = Vec::new();
+    if summary.failed > 0 {
+        reasons.push(format!(
+            "Failed {}/{} subtests",
+            summary.failed, summary.tests
+        ));
+    }
+    if let Some(reason) = &summary.bailout {
+        reasons.push(format!("Bailed out: {}", reason));
+    }
+    match summary.planned {
+        None => reasons.push("No plan found".to_string()),
+        Some(planned) if planned != summary.tests => {
+            reasons.push(format!("Planned {} but ran {}", planned, summary.tests))
+        }
+        _ => {}
+    }
+    if result.timed_out {
+        reasons.push("Timed out".to_string());
+    } else if !result.exit_status.success() {
+        reasons.push(result.exit_status.to_string());
+    }
+    if attempts > 1 {
+        reasons.push(format!("{} attempts", attempts));
+    }
+    reasons.join(", ")
+}
+
+/// The closing lines every built-in formatter prints.
+fn write_totals<W: Write>(out: &mut W, results: &[FileResult]) -> io::Result<()> {
+    let failed: Vec<_> = results.iter().filter(|result| !result.is_ok()).collect();
+    if failed.is_empty() {
+        writeln!(out, "All tests successful.")?;
+    } else {
+        writeln!(
+            out,
+            "Failed {}/{} test programs.",
+            failed.len(),
+            results.len()
+        )?;
+        for result in failed {
+            writeln!(out, "  {}: {}", result.path.display(), verdict(result))?;
+        }
+    }
+
+    let flaky: Vec<_> = results.iter().filter(|result| result.is_flaky()).collect();
+    if !flaky.is_empty() {
+        writeln!(
+            out,
+            "Flaky {}/{} test programs.",
+            flaky.len(),
+            results.len()
+        )?;
+        for result in flaky {
+            writeln!(out, "  {}: {}", result.path.display(), verdict(result))?;
+        }
+    }
+
+    let tests: u64 = results.iter().map(|result| result.summary.tests).sum();
+    writeln!(out, "Files={}, Tests={}", results.len(), tests)
+}
+
+/// One line per file, with a character per test point: `.` passed, `F`
+/// failed, `s` skipped, `t` TODO. Failing files' stderr follows their line.
+pub struct Dots<W> {
+    out: W,
+}
+
+impl<W: Write> Dots<W> {
+    pub fn new(out: W) -> Self {
+        Dots { out }
+    }
+}
+
+impl<W: Write> Formatter for Dots<W> {
+    fn file_start(&mut self, path: &Path) -> io::Result<()> {
+        write!(self.out, "{} ", path.display())?;
+        self.out.flush()
+    }
+
+    fn line(&mut self, _path: &Path, line: &Line) -> io::Result<()> {
+        if let Line::Test(test) = line {
+            let dot = match &test.directive {
+                Some(directive) => match directive.kind {
+                    DirectiveKind::Skip => "s",
+                    DirectiveKind::Todo => "t",
+                },
+                None if test.ok => ".",
+                None => "F",
+            };
+            self.out.write_all(dot.as_bytes())?;
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn retry(&mut self, attempt: &FileResult) -> io::Result<()> {
+        write!(self.out, " {}, retrying ", verdict(attempt))?;
+        self.out.flush()
+    }
+
+    fn file_end(&mut self, result: &FileResult) -> io::Result<()> {
+        writeln!(self.out, " {}", verdict(result))?;
+        if !result.is_ok() {
+            for stderr in &result.stderr {
+                self.out.write_all(stderr.text.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, results: &[FileResult]) -> io::Result<()> {
+        write_totals(&mut self.out, results)
+    }
+}
+
+/// Every line of TAP and stderr as it arrives, under a header for each file.
+pub struct Verbose<W> {
+    out: W,
+}
+
+impl<W: Write> Verbose<W> {
+    pub fn new(out: W) -> Self {
+        Verbose { out }
+    }
+}
+
+impl<W: Write> Formatter for Verbose<W> {
+    fn file_start(&mut self, path: &Path) -> io::Result<()> {
+        writeln!(self.out, "{} ..", path.display())
+    }
+
+    fn line(&mut self, _path: &Path, line: &Line) -> io::Result<()> {
+        writeln!(self.out, "{}", line)
+    }
+
+    fn stderr(&mut self, _path: &Path, text: &str) -> io::Result<()> {
+        self.out.write_all(text.as_bytes())
+    }
+
+    fn retry(&mut self, attempt: &FileResult) -> io::Result<()> {
+        writeln!(self.out, "{}, retrying", verdict(attempt))
+    }
+
+    fn file_end(&mut self, result: &FileResult) -> io::Result<()> {
+        writeln!(self.out, "{}", verdict(result))
+    }
+
+    fn finish(&mut self, results: &[FileResult]) -> io::Result<()> {
+        write_totals(&mut self.out, results)
+    }
+}
+
+/// Only the totals.
+pub struct Quiet<W> {
+    out: W,
+}
+
+impl<W: Write> Quiet<W> {
+    pub fn new(out: W) -> Self {
+        Quiet { out }
+    }
+}
+
+impl<W: Write> Formatter for Quiet<W> {
+    fn finish(&mut self, results: &[FileResult]) -> io::Result<()> {
+        write_totals(&mut self.out, results)
+    }
+}
+
+/// Only failing files, each with its failing test points and the comments,
+/// YAML and stderr that followed them.
+pub struct Failures<W> {
+    out: W,
+}
+
+impl<W: Write> Failures<W> {
+    pub fn new(out: W) -> Self {
+        Failures { out }
+    }
+}
+
+impl<W: Write> Formatter for Failures<W> {
+    fn file_end(&mut self, result: &FileResult) -> io::Result<()> {
+        if result.is_ok() {
+            return Ok(());
+        }
+
+        writeln!(self.out, "{} .. {}", result.path.display(), verdict(result))?;
+        let lines = &result.document.lines;
+        // Stderr from before the first test, like a compile error, counts too.
+        let explains_failure = |test: Option<usize>| match test.map(|test| &lines[test]) {
+            Some(Line::Test(test)) => !test.ok && test.directive.is_none(),
+            _ => true,
+        };
+        let mut stderr = result.stderr.iter().peekable();
+        let mut in_failure = false;
+        for (index, line) in lines.iter().enumerate() {
+            while let Some(chunk) = stderr.next_if(|chunk| chunk.line <= index) {
+                if explains_failure(chunk.test) {
+                    self.out.write_all(chunk.text.as_bytes())?;
+                }
+            }
+            match line {
+                Line::Test(test) => {
+                    in_failure = !test.ok && test.directive.is_none();
+                    if in_failure {
+                        writeln!(self.out, "{}", line)?;
+                    }
+                }
+                Line::Comment(_) | Line::Yaml(_) if in_failure => writeln!(self.out, "{}", line)?,
+                Line::Bailout(_) => writeln!(self.out, "{}", line)?,
+                _ => {}
+            }
+        }
+        for chunk in stderr.filter(|chunk| explains_failure(chunk.test)) {
+            self.out.write_all(chunk.text.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, results: &[FileResult]) -> io::Result<()> {
+        write_totals(&mut self.out, results)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::harness::tests::{flaky, lockstep, script, Lockstep};
+    use crate::harness::Harness;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A writer the test can still read after handing it to the harness.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    fn run<F: Formatter + 'static>(formatter: F) {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            script(
+                dir.path(),
+                "a.t",
+                "1..3\nok 1\nok 2 # SKIP later\nnot ok 3 # TODO soon\n",
+                0,
+            ),
+            script(
+                dir.path(),
+                "b.t",
+                "1..3\nok 1\nnot ok 2 - broken\n# got 1\nok 3\n",
+                1,
+            ),
+        ];
+        Harness::new().formatter(formatter).run(&files).unwrap();
+    }
+
+    #[test]
+    fn test_dots() {
+        let out = Shared::default();
+        run(Dots::new(out.clone()));
+
+        let text = out.text();
+        let lines: Vec<_> = text.lines().collect();
+        assert!(lines[0].ends_with("a.t .st ok"));
+        assert!(lines[1].ends_with("b.t .F. Failed 1/3 subtests, exit status: 1"));
+        assert_eq!(lines[2], "Failed 1/2 test programs.");
+        assert!(lines[3].ends_with("b.t: Failed 1/3 subtests, exit status: 1"));
+        assert_eq!(lines[4], "Files=2, Tests=6");
+    }
+
+    #[test]
+    fn test_verbose() {
+        let out = Shared::default();
+        run(Verbose::new(out.clone()));
+
+        let text = out.text();
+        let lines: Vec<_> = text.lines().collect();
+        assert!(lines[0].ends_with("a.t .."));
+        assert_eq!(
+            &lines[1..6],
+            &[
+                "1..3",
+                "ok 1",
+                "ok 2 # SKIP later",
+                "not ok 3 # TODO soon",
+                "ok"
+            ]
+        );
+        assert_eq!(lines[10], "# got 1");
+    }
+
+    #[test]
+    fn test_quiet() {
+        let out = Shared::default();
+        run(Quiet::new(out.clone()));
+
+        assert_eq!(out.text().lines().count(), 3);
+        assert!(out.text().ends_with("Files=2, Tests=6\n"));
+    }
+
+    #[test]
+    fn test_failures() {
+        let out = Shared::default();
+        run(Failures::new(out.clone()));
+
+        let text = out.text();
+        let lines: Vec<_> = text.lines().collect();
+        assert!(lines[0].ends_with("b.t .. Failed 1/3 subtests, exit status: 1"));
+        assert_eq!(&lines[1..3], &["not ok 2 - broken", "# got 1"]);
+        assert_eq!(lines[3], "Failed 1/2 test programs.");
+    }
+
+    #[test]
+    fn test_failures_stderr() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = lockstep(
+            dir.path(),
+            "diag.t",
+            "say 1..2\nsay 'not ok 1'\nwarn '# wrong'\nsay 'ok 2'\nwarn '# fine'\n",
+        );
+        let out = Shared::default();
+        Harness::new()
+            .formatter(Lockstep::new(Failures::new(out.clone()), &path))
+            .run(&[&path])
+            .unwrap();
+
+        let text = out.text();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(&lines[1..3], &["not ok 1", "# wrong"]);
+        assert_eq!(lines[3], "Failed 1/1 test programs.");
+    }
+
+    #[test]
+    fn test_flaky() {
+        let dir = tempfile::tempdir().unwrap();
+        let once = flaky(dir.path(), "once.t", 1);
+        let always = flaky(dir.path(), "always.t", 10);
+        let out = Shared::default();
+        Harness::new()
+            .formatter(Dots::new(out.clone()))
+            .retries(1)
+            .run(&[&once, &always])
+            .unwrap();
+
+        let text = out.text();
+        let lines: Vec<_> = text.lines().collect();
+        assert!(
+            lines[0].ends_with("once.t F Failed 1/1 subtests, retrying . flaky pass on attempt 2")
+        );
+        assert!(lines[1].ends_with(
+            "always.t F Failed 1/1 subtests, retrying F Failed 1/1 subtests, 2 attempts"
+        ));
+        assert_eq!(lines[2], "Failed 1/2 test programs.");
+        assert_eq!(lines[4], "Flaky 1/2 test programs.");
+        assert!(lines[5].ends_with("once.t: flaky pass on attempt 2"));
+    }
+}
@@ -0,0 +1,315 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::harness::FileResult;
+use crate::summary::Summary;
+
+/// Conditions a run's combined `Summary` must meet for CI to pass, for when
+/// some failures are tolerable, e.g. during a migration. `check_files` holds
+/// each file to the rest.
+///
+/// ```
+/// use tap_pest::gate::Gates;
+/// use tap_pest::summary::Summary;
+///
+/// let gates = Gates::new().max_failures(2).forbid_skips();
+/// let summary = Summary {
+///     planned: Some(10),
+///     tests: 10,
+///     passed: 9,
+///     failed: 1,
+///     ..Summary::default()
+/// };
+/// assert!(gates.check(&summary).is_empty());
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Gates {
+    pub max_failures: Option<u64>,
+    /// Between 0 and 1.
+    pub min_pass_rate: Option<f64>,
+    /// TODO tests that pass should have their TODO removed.
+    pub forbid_todo_passed: bool,
+    pub forbid_skips: bool,
+    pub require_plan: bool,
+}
+
+/// A gate a run didn't get through.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tripped {
+    Failures {
+        failed: u64,
+        max: u64,
+    },
+    PassRate {
+        rate: f64,
+        min: f64,
+    },
+    TodoPassed(u64),
+    Skipped(u64),
+    Plan {
+        path: PathBuf,
+        planned: Option<u64>,
+        tests: u64,
+    },
+    /// A file went wrong in a way a failure budget doesn't cover.
+    File {
+        path: PathBuf,
+        reason: String,
+    },
+    /// Bailing out trips whatever the gates are.
+    Bailout(String),
+}
+
+impl fmt::Display for Tripped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Tripped::Failures { failed, max } => {
+                write!(f, "max_failures: {} failed, {} allowed", failed, max)
+            }
+            Tripped::PassRate { rate, min } => write!(
+                f,
+                "min_pass_rate: {:.1}% passed, {:.1}% needed",
+                rate * 100.0,
+                min * 100.0
+            ),
+            Tripped::TodoPassed(count) => {
+                write!(f, "forbid_todo_passed: {} TODO tests passed", count)
+            }
+            Tripped::Skipped(count) => write!(f, "forbid_skips: {} tests skipped", count),
+            Tripped::Plan {
+                path,
+                planned: Some(planned),
+                tests,
+            } => write!(
+                f,
+                "{}: planned {} but ran {}",
+                path.display(),
+                planned,
+                tests
+            ),
+            Tripped::Plan {
+                path,
+                planned: None,
+                ..
+            } => write!(f, "{}: no plan found", path.display()),
+            Tripped::File { path, reason } => write!(f, "{}: {}", path.display(), reason),
+            Tripped::Bailout(reason) => write!(f, "bailed out: {}", reason),
+        }
+    }
+}
+
+impl Gates {
+    pub fn new() -> Self {
+        Gates::default()
+    }
+
+    pub fn max_failures(mut self, failures: u64) -> Self {
+        self.max_failures = Some(failures);
+        self
+    }
+
+    pub fn min_pass_rate(mut self, rate: f64) -> Self {
+        self.min_pass_rate = Some(rate);
+        self
+    }
+
+    pub fn forbid_todo_passed(mut self) -> Self {
+        self.forbid_todo_passed = true;
+        self
+    }
+
+    pub fn forbid_skips(mut self) -> Self {
+        self.forbid_skips = true;
+        self
+    }
+
+    pub fn require_plan(mut self) -> Self {
+        self.require_plan = true;
+        self
+    }
+
+    /// Whether there is a failure budget, in which case the gates rather
+    /// than each file passing decide the run.
+    pub fn allow_failures(&self) -> bool {
+        self.max_failures.is_some() || self.min_pass_rate.is_some()
+    }
+
+    /// The gates `summary` trips, none if it gets through. A run without
+    /// tests has a pass rate of 100%.
+    pub fn check(&self, summary: &Summary) -> Vec<Tripped> {
+        let mut tripped = Vec::new();
+        if let Some(reason) = &summary.bailout {
+            tripped.push(Tripped::Bailout(reason.clone()));
+        }
+        if let Some(max) = self.max_failures {
+            if summary.failed > max {
+                tripped.push(Tripped::Failures {
+                    failed: summary.failed,
+                    max,
+                });
+            }
+        }
+        if let Some(min) = self.min_pass_rate {
+            let rate = if summary.tests == 0 {
+                1.0
+            } else {
+                summary.passed as f64 / summary.tests as f64
+            };
+            if rate < min {
+                tripped.push(Tripped::PassRate { rate, min });
+            }
+        }
+        if self.forbid_todo_passed && summary.todo_passed > 0 {
+            tripped.push(Tripped::TodoPassed(summary.todo_passed));
+        }
+        if self.forbid_skips && summary.skipped > 0 {
+            tripped.push(Tripped::Skipped(summary.skipped));
+        }
+        tripped
+    }
+
+    /// The gates `results` trip one file at a time: `require_plan`, and with
+    /// a failure budget, which only covers failed tests, a missing or broken
+    /// plan, a timeout, a limit or an exit status the failures don't explain.
+    pub fn check_files(&self, results: &[FileResult]) -> Vec<Tripped> {
+        let mut tripped = Vec::new();
+        for result in results {
+            let summary = &result.summary;
+            if (self.require_plan || self.allow_failures()) && !summary.plan_matches() {
+                tripped.push(Tripped::Plan {
+                    path: result.path.clone(),
+                    planned: summary.planned,
+                    tests: summary.tests,
+                });
+            }
+            if !self.allow_failures() {
+                continue;
+            }
+            let mut reasons = Vec::new();
+            if result.timed_out {
+                reasons.push("timed out".to_string());
+            }
+            for limit in &result.exceeded {
+                reasons.push(format!("exceeded the {}", limit));
+            }
+            if !result.timed_out && !result.exit_explained() {
+                reasons.push(result.exit_status.to_string());
+            }
+            if !reasons.is_empty() {
+                tripped.push(Tripped::File {
+                    path: result.path.clone(),
+                    reason: reasons.join(", "),
+                });
+            }
+        }
+        tripped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::TapDocument;
+
+    fn summary(tap: &str) -> Summary {
+        Summary::from_document(&TapDocument::parse(tap).unwrap())
+    }
+
+    #[test]
+    fn test_check() {
+        let summary =
+            summary("1..6\nok 1\nnot ok 2\nnot ok 3\nok 4 # TODO later\nok 5 # SKIP no network\n");
+        let all = Gates::new()
+            .max_failures(1)
+            .min_pass_rate(0.9)
+            .forbid_todo_passed()
+            .forbid_skips()
+            .require_plan();
+
+        let tripped: Vec<_> = all
+            .check(&summary)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            tripped,
+            [
+                "max_failures: 2 failed, 1 allowed",
+                "min_pass_rate: 60.0% passed, 90.0% needed",
+                "forbid_todo_passed: 1 TODO tests passed",
+                "forbid_skips: 1 tests skipped",
+            ]
+        );
+
+        let lenient = Gates::new().max_failures(2).min_pass_rate(0.6);
+        assert!(lenient.check(&summary).is_empty());
+        assert!(Gates::new().check(&summary).is_empty());
+        assert!(lenient.allow_failures());
+        assert!(!Gates::new().forbid_skips().allow_failures());
+        assert_eq!(
+            Gates::new().check(&self::summary("1..2\nok 1\nBail out! db down\n")),
+            [Tripped::Bailout("db down".to_string())]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_files() {
+        use crate::limits::Limit;
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::ExitStatus;
+        use std::time::{Duration, SystemTime};
+
+        let result = |path: &str, tap: &str, code: i32| {
+            let document = TapDocument::parse(tap).unwrap().into_owned();
+            FileResult {
+                path: PathBuf::from(path),
+                summary: Summary::from_document(&document),
+                output: tap.as_bytes().to_vec(),
+                document,
+                exit_status: ExitStatus::from_raw(code << 8),
+                start_time: SystemTime::now(),
+                duration: Duration::ZERO,
+                timed_out: false,
+                stderr: Vec::new(),
+                exceeded: Vec::new(),
+                attempts: Vec::new(),
+            }
+        };
+        let mut crashed = result("t/crash.t", "1..1\nok 1\n", 0);
+        crashed.exit_status = ExitStatus::from_raw(11);
+        let mut exceeded = result("t/big.t", "1..2\nok 1\nnot ok 2\n", 1);
+        exceeded.exceeded.push(Limit::Cpu(1));
+        let results = [
+            result("t/ok.t", "1..2\nok 1\nnot ok 2\n", 1),
+            result("t/short.t", "1..2\nok 1\n", 0),
+            result("t/none.t", "ok 1\n", 0),
+            result("t/died.t", "1..1\nnot ok 1\n", 255),
+            crashed,
+            exceeded,
+        ];
+
+        let show = |gates: Gates| {
+            gates
+                .check_files(&results)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            show(Gates::new().max_failures(10)),
+            [
+                "t/short.t: planned 2 but ran 1",
+                "t/none.t: no plan found",
+                "t/died.t: exit status: 255",
+                "t/crash.t: signal: 11 (SIGSEGV)",
+                "t/big.t: exceeded the CPU time limit of 1s",
+            ]
+        );
+        assert_eq!(
+            show(Gates::new().require_plan()),
+            ["t/short.t: planned 2 but ran 1", "t/none.t: no plan found"]
+        );
+        assert!(show(Gates::new()).is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+use crate::document::{
+    Bailout, Comment, Line, Plan, TapDocument, TestPoint, Unknown, Version, Yaml,
+};
+
+/// Callbacks for consuming TAP without holding on to a whole `TapDocument`, in
+/// the spirit of Perl's `TAP::Parser` callbacks. Every method does nothing by
+/// default, so implementors only override the events they care about.
+///
+/// Driven by `TapDocument::visit` and `StreamParser::drive`.
+pub trait TapHandler {
+    fn on_version(&mut self, _version: &Version) {}
+    fn on_plan(&mut self, _plan: &Plan) {}
+    fn on_test(&mut self, _test: &TestPoint) {}
+    fn on_comment(&mut self, _comment: &Comment) {}
+    fn on_bailout(&mut self, _bailout: &Bailout) {}
+    fn on_unknown(&mut self, _unknown: &Unknown) {}
+    fn on_yaml(&mut self, _yaml: &Yaml) {}
+    /// Called once after the last line.
+    fn on_end(&mut self) {}
+}
+
+impl<'a> Line<'a> {
+    pub fn dispatch<H: TapHandler + ?Sized>(&self, handler: &mut H) {
+        match self {
+            Line::Version(version) => handler.on_version(version),
+            Line::Plan(plan) => handler.on_plan(plan),
+            Line::Test(test) => handler.on_test(test),
+            Line::Comment(comment) => handler.on_comment(comment),
+            Line::Bailout(bailout) => handler.on_bailout(bailout),
+            Line::Yaml(yaml) => handler.on_yaml(yaml),
+            Line::Unknown(unknown) => handler.on_unknown(unknown),
+        }
+    }
+}
+
+impl<'a> TapDocument<'a> {
+    pub fn visit<H: TapHandler + ?Sized>(&self, handler: &mut H) {
+        for line in &self.lines {
+            line.dispatch(handler);
+        }
+        handler.on_end();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Records each callback as a short string.
+    #[derive(Default)]
+    pub(crate) struct Recorder(pub Vec<String>);
+
+    impl TapHandler for Recorder {
+        fn on_version(&mut self, version: &Version) {
+            self.0.push(format!("version {}", version.version));
+        }
+
+        fn on_plan(&mut self, plan: &Plan) {
+            self.0.push(format!("plan {}", plan.count));
+        }
+
+        fn on_test(&mut self, test: &TestPoint) {
+            self.0.push(format!("test {} {:?}", test.ok, test.number));
+        }
+
+        fn on_comment(&mut self, comment: &Comment) {
+            self.0.push(format!("comment {:?}", comment.text));
+        }
+
+        fn on_bailout(&mut self, bailout: &Bailout) {
+            self.0.push(format!("bailout {:?}", bailout.reason));
+        }
+
+        fn on_unknown(&mut self, unknown: &Unknown) {
+            self.0.push(format!("unknown {:?}", unknown.text));
+        }
+
+        fn on_yaml(&mut self, yaml: &Yaml) {
+            self.0.push(format!("yaml {:?}", yaml.text));
+        }
+
+        fn on_end(&mut self) {
+            self.0.push("end".to_string());
+        }
+    }
+
+    pub(crate) const EXAMPLE: &str = r"TAP version 13
+1..3
+# starting
+ok 1 - first
+not ok 2 - second
+  ---
+  message: 'boom'
+  ...
+what is this
+Bail out! giving up
+";
+
+    #[test]
+    fn test_visit() {
+        let document = TapDocument::parse(EXAMPLE).unwrap();
+        let mut recorder = Recorder::default();
+        document.visit(&mut recorder);
+
+        assert_eq!(
+            recorder.0,
+            vec![
+                "version 13",
+                "plan 3",
+                "comment Some(\"starting\")",
+                "test true Some(1)",
+                "test false Some(2)",
+                "yaml \"  message: 'boom'\\n\"",
+                "unknown \"what is this\"",
+                "bailout Some(\"giving up\")",
+                "end",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_default_methods() {
+        struct CountTests(usize);
+
+        impl TapHandler for CountTests {
+            fn on_test(&mut self, _test: &TestPoint) {
+                self.0 += 1;
+            }
+        }
+
+        let mut counter = CountTests(0);
+        TapDocument::parse(EXAMPLE).unwrap().visit(&mut counter);
+
+        assert_eq!(counter.0, 2);
+    }
+}
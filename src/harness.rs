@@ -0,0 +1,1015 @@
+use std::collections::VecDeque;
+use std::env;
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use tempfile::TempDir;
+
+use crate::document::{Line, Span, TapDocument, TestPoint};
+use crate::formatter::{Dots, Formatter};
+use crate::limits::{Limit, Limits};
+use crate::source::Sources;
+use crate::stream::StreamParser;
+use crate::summary::Summary;
+
+/// Outcome of running one test program.
+#[derive(Clone, Debug)]
+pub struct FileResult {
+    pub path: PathBuf,
+    /// Everything the program printed, as parsed by `StreamParser`.
+    pub document: TapDocument<'static>,
+    /// The bytes the program wrote to stdout, which `document` was parsed
+    /// from.
+    pub output: Vec<u8>,
+    pub summary: Summary,
+    pub exit_status: ExitStatus,
+    pub start_time: SystemTime,
+    /// From starting the program until it exited.
+    pub duration: Duration,
+    /// The program was killed for running longer than `Harness::timeout`.
+    pub timed_out: bool,
+    /// What the program wrote to stderr, in the order it arrived.
+    pub stderr: Vec<Stderr>,
+    /// Resource limits the program ran into. Each also adds a failing test
+    /// point to the end of `document`.
+    pub exceeded: Vec<Limit>,
+    /// Earlier runs of the file that failed, oldest first, when
+    /// `Harness::retries` allowed running it again.
+    pub attempts: Vec<FileResult>,
+}
+
+/// A piece of a program's stderr, placed among its TAP.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stderr {
+    /// How many lines of TAP had arrived before it, so it belongs just before
+    /// `document.lines[line]`.
+    pub line: usize,
+    /// Index in `document.lines` of the latest test point when it arrived,
+    /// which is usually the test it explains.
+    pub test: Option<usize>,
+    pub text: String,
+}
+
+impl FileResult {
+    /// The TAP passed and the program exited successfully.
+    pub fn is_ok(&self) -> bool {
+        self.summary.is_ok() && self.exit_status.success() && !self.timed_out
+    }
+
+    /// Exited successfully, or with the number of failed tests as
+    /// `Test::More` does, rather than crashing or dying for some other
+    /// reason.
+    pub fn exit_explained(&self) -> bool {
+        let failed = self.summary.failed.min(254) as i32;
+        self.exit_status.code() == Some(failed) || self.exit_status.success()
+    }
+
+    /// Passed, but only after failing at least once.
+    pub fn is_flaky(&self) -> bool {
+        self.is_ok() && !self.attempts.is_empty()
+    }
+
+    /// Everything written to stderr while `document.lines[test]` was the
+    /// latest test point, such as `Test::More`'s failure diagnostics.
+    pub fn stderr_for(&self, test: usize) -> String {
+        self.stderr
+            .iter()
+            .filter(|stderr| stderr.test == Some(test))
+            .map(|stderr| stderr.text.as_str())
+            .collect()
+    }
+}
+
+/// Runs test programs and parses their TAP as it arrives, in the spirit of
+/// `prove`. Progress goes to a `Formatter`, `Dots` on stdout unless another
+/// one is given. How each file is run is up to `Sources`.
+///
+/// Stderr is captured too, and reaches the formatter interleaved with the
+/// TAP in the order it arrived.
+///
+/// With more than one job, files run in parallel and the formatter hears
+/// about each one once it has finished, in the order they were given.
+pub struct Harness {
+    formatter: Box<dyn Formatter>,
+    jobs: usize,
+    runner: Runner,
+}
+
+/// Everything needed to run one file, shared by the jobs.
+struct Runner {
+    sources: Sources,
+    timeout: Option<Duration>,
+    env: Vec<(OsString, OsString)>,
+    limits: Limits,
+    sandbox: bool,
+    retries: usize,
+}
+
+impl Default for Harness {
+    fn default() -> Self {
+        Harness::new()
+    }
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        Harness {
+            formatter: Box::new(Dots::new(io::stdout())),
+            jobs: 1,
+            runner: Runner {
+                sources: Sources::new(),
+                timeout: None,
+                env: Vec::new(),
+                limits: Limits::new(),
+                sandbox: false,
+                retries: 0,
+            },
+        }
+    }
+
+    pub fn formatter<F: Formatter + 'static>(mut self, formatter: F) -> Self {
+        self.formatter = Box::new(formatter);
+        self
+    }
+
+    pub fn sources(mut self, sources: Sources) -> Self {
+        self.runner.sources = sources;
+        self
+    }
+
+    /// How many programs to run at once.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Kill programs that run for longer than `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.runner.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets an environment variable for every program.
+    pub fn env<K: Into<OsString>, V: Into<OsString>>(mut self, key: K, value: V) -> Self {
+        self.runner.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Resource limits for every program. Only supported on Linux; elsewhere
+    /// `run` fails if any are set.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.runner.limits = limits;
+        self
+    }
+
+    /// Run each program in a fresh temporary directory, removed when it
+    /// exits, so tests can't trip over each other's files.
+    pub fn sandbox(mut self, sandbox: bool) -> Self {
+        self.runner.sandbox = sandbox;
+        self
+    }
+
+    /// Rerun a failing file up to `retries` more times. A file that passes
+    /// on a retry is a flaky pass, and keeps its failed attempts in
+    /// `FileResult::attempts`.
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.runner.retries = retries;
+        self
+    }
+
+    pub fn run<P: AsRef<Path>>(&mut self, files: &[P]) -> io::Result<Vec<FileResult>> {
+        let results = if self.jobs > 1 {
+            self.run_parallel(files)?
+        } else {
+            self.run_serial(files)?
+        };
+        self.formatter.finish(&results)?;
+
+        Ok(results)
+    }
+
+    fn run_serial<P: AsRef<Path>>(&mut self, files: &[P]) -> io::Result<Vec<FileResult>> {
+        let mut results = Vec::new();
+        for file in files {
+            let path = file.as_ref();
+            self.formatter.file_start(path)?;
+            let result = self.runner.run(path, Some(self.formatter.as_mut()))?;
+            self.formatter.file_end(&result)?;
+            results.push(result);
+        }
+
+        Ok(results)
+    }
+
+    fn run_parallel<P: AsRef<Path>>(&mut self, files: &[P]) -> io::Result<Vec<FileResult>> {
+        let queue: VecDeque<_> = files
+            .iter()
+            .map(|file| file.as_ref().to_path_buf())
+            .enumerate()
+            .collect();
+        let queue = Mutex::new(queue);
+        let jobs = self.jobs.min(files.len());
+        let runner = &self.runner;
+        let formatter = &mut self.formatter;
+
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            for _ in 0..jobs {
+                let sender = sender.clone();
+                let queue = &queue;
+                scope.spawn(move || loop {
+                    let next = queue.lock().unwrap().pop_front();
+                    let Some((index, path)) = next else {
+                        break;
+                    };
+                    if sender.send((index, runner.run(&path, None))).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(sender);
+
+            // Report finished files in order, holding back any that finish
+            // before an earlier one.
+            let mut finished: Vec<Option<io::Result<FileResult>>> =
+                files.iter().map(|_| None).collect();
+            let mut results = Vec::new();
+            for (index, result) in receiver {
+                finished[index] = Some(result);
+                while let Some(Some(result)) = finished.get_mut(results.len()).map(Option::take) {
+                    let reported = result.and_then(|result| {
+                        replay(formatter.as_mut(), &result)?;
+                        Ok(result)
+                    });
+                    match reported {
+                        Ok(result) => results.push(result),
+                        Err(err) => {
+                            queue.lock().unwrap().clear();
+                            return Err(err);
+                        }
+                    }
+                }
+            }
+
+            Ok(results)
+        })
+    }
+}
+
+impl Runner {
+    /// Runs the file at `path`, again while it fails and retries are left.
+    /// Errors name the file.
+    fn run(
+        &self,
+        path: &Path,
+        formatter: Option<&mut (dyn Formatter + '_)>,
+    ) -> io::Result<FileResult> {
+        self.attempt(path, formatter)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
+    }
+
+    fn attempt(
+        &self,
+        path: &Path,
+        mut formatter: Option<&mut (dyn Formatter + '_)>,
+    ) -> io::Result<FileResult> {
+        let mut attempts = Vec::new();
+        loop {
+            let (command, work_dir) = self.command(path)?;
+            let mut result = execute(
+                path,
+                command,
+                self.timeout,
+                &self.limits,
+                formatter.as_deref_mut(),
+            )?;
+            drop(work_dir);
+
+            if result.is_ok() || attempts.len() >= self.retries {
+                result.attempts = attempts;
+                return Ok(result);
+            }
+            if let Some(formatter) = formatter.as_deref_mut() {
+                formatter.retry(&result)?;
+            }
+            attempts.push(result);
+        }
+    }
+
+    /// The command for `path`, and the temporary directory it runs in when
+    /// sandboxed, which is removed when dropped.
+    fn command(&self, path: &Path) -> io::Result<(Command, Option<TempDir>)> {
+        let (mut command, work_dir) = if self.sandbox {
+            let work_dir = tempfile::Builder::new().prefix("tap-pest-").tempdir()?;
+            let mut command = self.sources.command(&env::current_dir()?.join(path))?;
+            command.current_dir(work_dir.path());
+            (command, Some(work_dir))
+        } else {
+            (self.sources.command(path)?, None)
+        };
+        command.envs(self.env.iter().map(|(key, value)| (key, value)));
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        self.limits.apply(&mut command)?;
+        Ok((command, work_dir))
+    }
+}
+
+/// Tells `formatter` about a file that has already finished, and any
+/// attempts before it.
+pub(crate) fn replay(formatter: &mut dyn Formatter, result: &FileResult) -> io::Result<()> {
+    formatter.file_start(&result.path)?;
+    for attempt in &result.attempts {
+        replay_output(formatter, attempt)?;
+        formatter.retry(attempt)?;
+    }
+    replay_output(formatter, result)?;
+    formatter.file_end(result)
+}
+
+fn replay_output(formatter: &mut dyn Formatter, result: &FileResult) -> io::Result<()> {
+    let mut stderr = result.stderr.iter().peekable();
+    for (index, line) in result.document.lines.iter().enumerate() {
+        while let Some(chunk) = stderr.next_if(|chunk| chunk.line <= index) {
+            formatter.stderr(&result.path, &chunk.text)?;
+        }
+        formatter.line(&result.path, line)?;
+    }
+    for chunk in stderr {
+        formatter.stderr(&result.path, &chunk.text)?;
+    }
+    Ok(())
+}
+
+/// What the reader threads pass back.
+enum Output {
+    Line(io::Result<Line<'static>>),
+    Stderr(String),
+    /// Bytes of stdout, as read.
+    Raw(Vec<u8>),
+}
+
+/// Passes on everything read from stdout, so the output can be kept as it
+/// was as well as parsed.
+struct Tee<R> {
+    reader: R,
+    sender: mpsc::Sender<Output>,
+}
+
+impl<R: Read> Read for Tee<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        if read > 0 {
+            let _ = self.sender.send(Output::Raw(buf[..read].to_vec()));
+        }
+        Ok(read)
+    }
+}
+
+/// Runs `command`, telling `formatter` (if any) about its TAP and stderr as
+/// they arrive.
+fn execute(
+    path: &Path,
+    mut command: Command,
+    timeout: Option<Duration>,
+    limits: &Limits,
+    mut formatter: Option<&mut (dyn Formatter + '_)>,
+) -> io::Result<FileResult> {
+    let start_time = SystemTime::now();
+    let started = Instant::now();
+    let deadline = timeout.map(|timeout| started + timeout);
+    let mut child = command.spawn()?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+
+    // Read on other threads so a program that goes quiet can still be timed
+    // out, and so the two pipes arrive in order. After a timeout the readers
+    // are left behind, since anything the program started may hold the pipes
+    // open.
+    let (sender, receiver) = mpsc::channel();
+    let stderr_sender = sender.clone();
+    let stdout = Tee {
+        reader: stdout,
+        sender: sender.clone(),
+    };
+    thread::spawn(move || {
+        for line in StreamParser::new(BufReader::new(stdout)).timed() {
+            let failed = line.is_err();
+            if sender.send(Output::Line(line)).is_err() || failed {
+                break;
+            }
+        }
+    });
+    thread::spawn(move || {
+        let mut stderr = BufReader::new(stderr);
+        let mut line = Vec::new();
+        while let Ok(1..) = stderr.read_until(b'\n', &mut line) {
+            let text = String::from_utf8_lossy(&line).into_owned();
+            if stderr_sender.send(Output::Stderr(text)).is_err() {
+                break;
+            }
+            line.clear();
+        }
+    });
+
+    let mut document = TapDocument::default();
+    let mut raw = Vec::new();
+    let mut summary = Summary::default();
+    let mut chunks: Vec<Stderr> = Vec::new();
+    let mut test = None;
+    let mut timed_out = false;
+    loop {
+        let output = match deadline {
+            Some(deadline) => {
+                match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(output) => output,
+                    Err(RecvTimeoutError::Timeout) => {
+                        timed_out = true;
+                        break;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            None => match receiver.recv() {
+                Ok(output) => output,
+                Err(_) => break,
+            },
+        };
+
+        let handled = match output {
+            Output::Line(line) => {
+                // Output that can't be read fails this file, not the run.
+                let line = line.unwrap_or_else(|err| {
+                    failure(&summary, format!("couldn't read the output: {}", err))
+                });
+                let handled = match formatter.as_mut() {
+                    Some(formatter) => formatter.line(path, &line),
+                    None => Ok(()),
+                };
+                if let Line::Test(_) = line {
+                    test = Some(document.lines.len());
+                }
+                line.dispatch(&mut summary);
+                document.lines.push(line);
+                handled
+            }
+            Output::Stderr(text) => {
+                let handled = match formatter.as_mut() {
+                    Some(formatter) => formatter.stderr(path, &text),
+                    None => Ok(()),
+                };
+                let line = document.lines.len();
+                match chunks.last_mut() {
+                    Some(last) if last.line == line => last.text += &text,
+                    _ => chunks.push(Stderr { line, test, text }),
+                }
+                handled
+            }
+            Output::Raw(bytes) => {
+                raw.extend(bytes);
+                Ok(())
+            }
+        };
+        if let Err(err) = handled {
+            // Don't leave the program blocked on a pipe nobody is reading.
+            let _ = child.kill();
+            child.wait()?;
+            return Err(err);
+        }
+    }
+    document.attach_durations();
+
+    let (exit_status, cpu) = wait(&mut child, deadline, &mut timed_out)?;
+    let exceeded = limits.exceeded(&exit_status, cpu, &chunks, document.lines.len(), timed_out);
+    for limit in &exceeded {
+        let line = failure(&summary, format!("exceeded the {}", limit));
+        if let Some(formatter) = formatter.as_mut() {
+            formatter.line(path, &line)?;
+        }
+        line.dispatch(&mut summary);
+        document.lines.push(line);
+    }
+
+    Ok(FileResult {
+        path: path.to_path_buf(),
+        document,
+        output: raw,
+        summary,
+        exit_status,
+        start_time,
+        duration: started.elapsed(),
+        timed_out,
+        stderr: chunks,
+        exceeded,
+        attempts: Vec::new(),
+    })
+}
+
+/// A failing test point the harness adds after those in `summary`.
+fn failure(summary: &Summary, description: String) -> Line<'static> {
+    Line::Test(TestPoint {
+        ok: false,
+        number: Some(summary.tests + 1),
+        description: Some(description.into()),
+        directive: None,
+        duration: None,
+        time: None,
+        span: Span::default(),
+    })
+}
+
+/// Waits for `child` to exit, killing it if it is still running at `deadline`,
+/// and returns the CPU time it used if that's known.
+fn wait(
+    child: &mut Child,
+    deadline: Option<Instant>,
+    timed_out: &mut bool,
+) -> io::Result<(ExitStatus, Option<Duration>)> {
+    if let Some(deadline) = deadline {
+        while !*timed_out {
+            if let Some(exited) = reap(child, false)? {
+                return Ok(exited);
+            }
+            *timed_out = Instant::now() >= deadline;
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = child.kill();
+    }
+    Ok(reap(child, true)?.expect("a blocking wait returns a status"))
+}
+
+/// Reaps `child` with `wait4`, for its CPU time, waiting for it to exit if
+/// `block` is set. `child` mustn't be waited for any other way afterwards.
+#[cfg(target_os = "linux")]
+fn reap(child: &mut Child, block: bool) -> io::Result<Option<(ExitStatus, Option<Duration>)>> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let options = if block { 0 } else { libc::WNOHANG };
+    let mut status = 0;
+    // SAFETY: rusage is plain integers, for which zero is valid.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: both pointers are to live locals, and the pid is our own
+        // child, which std hasn't reaped since it's only waited for here.
+        match unsafe { libc::wait4(child.id() as libc::pid_t, &mut status, options, &mut usage) } {
+            0 => return Ok(None),
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            _ => break,
+        }
+    }
+    let time = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    let cpu = time(usage.ru_utime) + time(usage.ru_stime);
+
+    Ok(Some((ExitStatus::from_raw(status), Some(cpu))))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reap(child: &mut Child, block: bool) -> io::Result<Option<(ExitStatus, Option<Duration>)>> {
+    let status = if block {
+        Some(child.wait()?)
+    } else {
+        child.try_wait()?
+    };
+    Ok(status.map(|status| (status, None)))
+}
+
+#[cfg(all(test, unix))]
+pub(crate) mod tests {
+    use super::*;
+    use crate::formatter::Quiet;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Writes an executable shell script that prints `output`.
+    pub(crate) fn script(dir: &Path, name: &str, output: &str, exit_code: i32) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(
+            &path,
+            format!(
+                "#!/bin/sh\ncat <<'TAP'\n{}TAP\nexit {}\n",
+                output, exit_code
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let passing = script(dir.path(), "pass.t", "1..2\nok 1\nok 2 - two\n", 0);
+        let failing = script(dir.path(), "fail.t", "1..2\nok 1\nnot ok 2\n", 0);
+        let crashing = script(dir.path(), "crash.t", "1..1\nok 1\n", 3);
+
+        let results = Harness::new()
+            .formatter(Quiet::new(io::sink()))
+            .run(&[&passing, &failing, &crashing])
+            .unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].path, passing);
+        assert!(results[0].is_ok());
+        assert_eq!(results[0].summary.passed, 2);
+        assert_eq!(results[0].document.lines.len(), 3);
+        assert!(results[0]
+            .document
+            .tests()
+            .all(|test| test.duration.is_some()));
+        assert!(!results[1].is_ok());
+        assert_eq!(results[1].summary.failed, 1);
+        assert!(results[2].summary.is_ok());
+        assert_eq!(results[2].exit_status.code(), Some(3));
+        assert!(!results[2].is_ok());
+    }
+
+    #[test]
+    fn test_run_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = Harness::new()
+            .formatter(Quiet::new(io::sink()))
+            .run(&[dir.path().join("missing.t")]);
+
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("missing.t: "));
+    }
+
+    #[test]
+    fn test_bad_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let bad = script(
+            dir.path(),
+            "bad.t",
+            "1..3\nok 99999999999999999999999\nok 2 - slow # time=99999999999999999999s\nok 3\n",
+            0,
+        );
+        let good = script(dir.path(), "good.t", "1..1\nok 1\n", 0);
+
+        for jobs in [1, 2] {
+            let results = Harness::new()
+                .formatter(Quiet::new(io::sink()))
+                .jobs(jobs)
+                .run(&[&bad, &good])
+                .unwrap();
+
+            assert!(matches!(
+                &results[0].document.lines[1],
+                Line::Unknown(unknown) if unknown.text == "ok 99999999999999999999999"
+            ));
+            assert!(matches!(
+                &results[0].document.lines[2],
+                Line::Test(test) if test.description.as_deref()
+                    == Some("slow # time=99999999999999999999s")
+            ));
+            assert_eq!(results[0].summary.tests, 2);
+            assert!(!results[0].is_ok());
+            assert!(results[1].is_ok());
+        }
+    }
+
+    #[test]
+    fn test_jobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let slow = dir.path().join("slow.t");
+        fs::write(&slow, "#!/bin/sh\nsleep 0.3\necho 1..1\necho ok 1\n").unwrap();
+        let fast = script(dir.path(), "fast.t", "1..1\nnot ok 1\n", 0);
+        let files = [&slow, &fast, &slow, &fast];
+
+        let results = Harness::new()
+            .formatter(Quiet::new(io::sink()))
+            .jobs(4)
+            .run(&files)
+            .unwrap();
+
+        // In the order given, though the slow ones finish last.
+        let paths: Vec<_> = results.iter().map(|result| &result.path).collect();
+        assert_eq!(paths, files);
+        assert!(results[0].is_ok());
+        assert_eq!(results[1].summary.failed, 1);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let hangs = dir.path().join("hangs.t");
+        fs::write(
+            &hangs,
+            "#!/bin/sh\necho 1..2\necho ok 1\nsleep 5\necho ok 2\n",
+        )
+        .unwrap();
+        let quiet = dir.path().join("quiet.t");
+        fs::write(
+            &quiet,
+            "#!/bin/sh\necho 1..1\necho ok 1\nexec >&-\nsleep 5\n",
+        )
+        .unwrap();
+
+        let started = Instant::now();
+        let results = Harness::new()
+            .formatter(Quiet::new(io::sink()))
+            .timeout(Duration::from_millis(200))
+            .run(&[&hangs, &quiet])
+            .unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(results.iter().all(|result| result.timed_out));
+        assert_eq!(results[0].summary.passed, 1);
+        assert!(!results[1].is_ok());
+        assert_eq!(crate::formatter::verdict(&results[1]), "Timed out");
+    }
+
+    #[test]
+    fn test_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("env.t");
+        fs::write(&path, "#!/bin/sh\necho 1..1\necho \"ok 1 - $GREETING\"\n").unwrap();
+
+        let results = Harness::new()
+            .formatter(Quiet::new(io::sink()))
+            .env("GREETING", "hello")
+            .run(&[&path])
+            .unwrap();
+
+        let test = results[0].document.tests().next().unwrap();
+        assert_eq!(test.description.as_deref(), Some("hello"));
+    }
+
+    #[test]
+    fn test_stderr() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = lockstep(
+            dir.path(),
+            "stderr.t",
+            "warn loading\nsay 1..2\nsay 'ok 1'\nsay 'not ok 2'\n\
+             warn '#   Failed test 2'\nwarn '#   got: 1'\nsay '# done'\n",
+        );
+
+        let results = Harness::new()
+            .formatter(Lockstep::new(Quiet::new(io::sink()), &path))
+            .run(&[&path, &path])
+            .unwrap();
+
+        for result in &results {
+            assert_eq!(
+                result.stderr,
+                vec![
+                    Stderr {
+                        line: 0,
+                        test: None,
+                        text: "loading\n".to_string()
+                    },
+                    Stderr {
+                        line: 3,
+                        test: Some(2),
+                        text: "#   Failed test 2\n#   got: 1\n".to_string()
+                    },
+                ]
+            );
+            assert_eq!(result.stderr_for(2), "#   Failed test 2\n#   got: 1\n");
+            assert_eq!(result.stderr_for(1), "");
+        }
+
+        // Running in parallel, each file still gets all of its own.
+        let path = dir.path().join("parallel.t");
+        fs::write(
+            &path,
+            "#!/bin/sh\necho 1..1\necho one >&2\necho ok 1\necho two >&2\n",
+        )
+        .unwrap();
+        let results = Harness::new()
+            .formatter(Quiet::new(io::sink()))
+            .jobs(2)
+            .run(&[&path, &path])
+            .unwrap();
+        for result in &results {
+            let text: String = result
+                .stderr
+                .iter()
+                .map(|stderr| stderr.text.as_str())
+                .collect();
+            assert_eq!(text, "one\ntwo\n");
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    // The scripts that run out of files or memory print no TAP before the
+    // error, so it can't arrive early.
+    #[test]
+    fn test_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let spins = dir.path().join("spins.t");
+        fs::write(
+            &spins,
+            "#!/bin/sh\necho 1..1\necho ok 1\nwhile :; do :; done\n",
+        )
+        .unwrap();
+        let opens = dir.path().join("opens.t");
+        // paste opens all of its files at once.
+        fs::write(
+            &opens,
+            "#!/bin/sh\npaste $(for i in $(seq 100); do echo /dev/null; done) || exit 1\n\
+             echo 1..1\necho ok 1\n",
+        )
+        .unwrap();
+        let grows = dir.path().join("grows.t");
+        fs::write(
+            &grows,
+            "#!/bin/sh\ndd if=/dev/zero of=/dev/null bs=1G count=1 || exit 1\necho 1..1\necho ok 1\n",
+        )
+        .unwrap();
+
+        let limits = Limits::new().cpu(1).open_files(32).address_space(256 << 20);
+        let results = Harness::new()
+            .formatter(Quiet::new(io::sink()))
+            .limits(limits)
+            .timeout(Duration::from_secs(10))
+            .run(&[&spins, &opens, &grows])
+            .unwrap();
+
+        assert_eq!(results[0].exceeded, [Limit::Cpu(1)]);
+        assert!(!results[0].timed_out);
+        assert_eq!(results[0].summary.failed, 1);
+        let last = results[0].document.tests().last().unwrap();
+        assert_eq!(
+            last.description.as_deref(),
+            Some("exceeded the CPU time limit of 1s")
+        );
+        assert_eq!(last.number, Some(2));
+        assert_eq!(results[1].exceeded, [Limit::OpenFiles(32)]);
+        assert!(!results[1].is_ok());
+        assert_eq!(results[2].exceeded, [Limit::AddressSpace(256 << 20)]);
+
+        let results = Harness::new()
+            .formatter(Quiet::new(io::sink()))
+            .limits(Limits::new().open_files(256))
+            .run(&[&opens])
+            .unwrap();
+        assert!(results[0].exceeded.is_empty());
+        assert!(results[0].is_ok());
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pwd.t");
+        fs::write(
+            &path,
+            "#!/bin/sh\necho 1..1\ntouch mess\necho \"ok 1 - $PWD\"\n",
+        )
+        .unwrap();
+
+        let results = Harness::new()
+            .formatter(Quiet::new(io::sink()))
+            .sandbox(true)
+            .run(&[&path, &path])
+            .unwrap();
+
+        let work_dirs: Vec<_> = results
+            .iter()
+            .map(|result| {
+                let test = result.document.tests().next().unwrap();
+                PathBuf::from(test.description.as_deref().unwrap())
+            })
+            .collect();
+        assert_ne!(work_dirs[0], work_dirs[1]);
+        for work_dir in &work_dirs {
+            assert!(work_dir.starts_with(env::temp_dir()));
+            assert!(!work_dir.exists());
+        }
+        assert!(!env::current_dir().unwrap().join("mess").exists());
+    }
+
+    /// A script that fails until it has run `failures` times.
+    /// Writes a shell script that, after each line it writes with `say` to
+    /// stdout or `warn` to stderr, waits for a `Lockstep` formatter to see it,
+    /// so where stderr lands among the TAP doesn't depend on timing. Each
+    /// `say` must be one whole line of TAP.
+    pub(crate) fn lockstep(dir: &Path, name: &str, body: &str) -> PathBuf {
+        let path = dir.join(name);
+        let fifo = fifo(&path);
+        let status = Command::new("mkfifo").arg(&fifo).status().unwrap();
+        assert!(status.success());
+        // A process forked elsewhere in the tests can hold a copy of the
+        // writing end for a moment, so reading nothing doesn't count.
+        fs::write(
+            &path,
+            format!(
+                "#!/bin/sh\nwait_for_harness() {{ until read _ < '{0}'; do :; done; }}\n\
+                 say() {{ echo \"$1\"; wait_for_harness; }}\n\
+                 warn() {{ echo \"$1\" >&2; wait_for_harness; }}\n{1}",
+                fifo.display(),
+                body
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn fifo(script: &Path) -> PathBuf {
+        let mut fifo = script.as_os_str().to_owned();
+        fifo.push(".fifo");
+        PathBuf::from(fifo)
+    }
+
+    /// Passes everything on to `inner`, letting the `lockstep` script at
+    /// `script` go on after each line. Only works with one job.
+    pub(crate) struct Lockstep<F> {
+        pub(crate) inner: F,
+        fifo: PathBuf,
+    }
+
+    impl<F> Lockstep<F> {
+        pub(crate) fn new(inner: F, script: &Path) -> Self {
+            Lockstep {
+                inner,
+                fifo: fifo(script),
+            }
+        }
+    }
+
+    impl<F: Formatter> Formatter for Lockstep<F> {
+        fn file_start(&mut self, path: &Path) -> io::Result<()> {
+            self.inner.file_start(path)
+        }
+
+        fn line(&mut self, path: &Path, line: &Line) -> io::Result<()> {
+            self.inner.line(path, line)?;
+            fs::write(&self.fifo, "\n")
+        }
+
+        fn stderr(&mut self, path: &Path, text: &str) -> io::Result<()> {
+            self.inner.stderr(path, text)?;
+            fs::write(&self.fifo, "\n")
+        }
+
+        fn retry(&mut self, attempt: &FileResult) -> io::Result<()> {
+            self.inner.retry(attempt)
+        }
+
+        fn file_end(&mut self, result: &FileResult) -> io::Result<()> {
+            self.inner.file_end(result)
+        }
+
+        fn finish(&mut self, results: &[FileResult]) -> io::Result<()> {
+            self.inner.finish(results)
+        }
+    }
+
+    pub(crate) fn flaky(dir: &Path, name: &str, failures: usize) -> PathBuf {
+        let path = dir.join(name);
+        let count = dir.join(format!("{}.count", name));
+        fs::write(
+            &path,
+            format!(
+                "#!/bin/sh\necho x >> '{}'\necho 1..1\n\
+                 if [ $(wc -l < '{}') -gt {} ]; then echo ok 1; else echo not ok 1; fi\n",
+                count.display(),
+                count.display(),
+                failures
+            ),
+        )
+        .unwrap();
+        path
+    }
+
+    #[test]
+    fn test_retries() {
+        for jobs in &[1, 2] {
+            let dir = tempfile::tempdir().unwrap();
+            let once = flaky(dir.path(), "once.t", 1);
+            let always = flaky(dir.path(), "always.t", 10);
+            let steady = script(dir.path(), "steady.t", "1..1\nok 1\n", 0);
+
+            let results = Harness::new()
+                .formatter(Quiet::new(io::sink()))
+                .jobs(*jobs)
+                .retries(2)
+                .run(&[&once, &always, &steady])
+                .unwrap();
+
+            assert!(results[0].is_ok());
+            assert!(results[0].is_flaky());
+            assert_eq!(results[0].attempts.len(), 1);
+            assert_eq!(results[0].attempts[0].summary.failed, 1);
+            assert!(!results[1].is_ok());
+            assert!(!results[1].is_flaky());
+            assert_eq!(results[1].attempts.len(), 2);
+            assert!(results[2].is_ok());
+            assert!(!results[2].is_flaky());
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use std::io::{self, Write};
+
+use crate::diff::Outcome;
+use crate::document::Line;
+use crate::formatter::verdict;
+use crate::harness::FileResult;
+use crate::summary::Summary;
+
+/// Writes `results` as JUnit XML for CI servers: a `<testsuite>` per file and
+/// a `<testcase>` per test point, with the `time=` the harness measured. The
+/// comments and YAML after a failing test point go in its `<failure>`.
+/// Stderr goes in the `<system-err>` of the test point it followed, or of the
+/// file when it came before any. Test points that failed in earlier attempts
+/// at a retried file get a `<flakyFailure>` for each, or a `<rerunFailure>`
+/// if they still fail.
+///
+/// A file that goes wrong as a whole, like a bailout, a bad plan or a bad
+/// exit status, gets one more failing test case named after the file.
+pub fn write<W: Write>(mut out: W, results: &[FileResult]) -> io::Result<()> {
+    let total = Summary::total(results.iter().map(|result| &result.summary));
+    let failed_alone = results.iter().filter(|result| failed_alone(result)).count() as u64;
+    let time: f64 = results
+        .iter()
+        .map(|result| result.duration.as_secs_f64())
+        .sum();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<testsuites tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+        total.tests + failed_alone,
+        total.failed + failed_alone,
+        total.skipped,
+        time
+    )?;
+    for result in results {
+        write_suite(&mut out, result)?;
+    }
+    writeln!(out, "</testsuites>")
+}
+
+fn failed_alone(result: &FileResult) -> bool {
+    let summary = &result.summary;
+    summary.bailout.is_some()
+        || !summary.plan_matches()
+        || result.timed_out
+        || !result.exit_explained()
+}
+
+/// Whether test `number` failed in `result`.
+fn failed(result: &FileResult, number: u64) -> bool {
+    result.document.tests().enumerate().any(|(index, test)| {
+        test.number.unwrap_or(index as u64 + 1) == number && Outcome::of(test) == Outcome::Fail
+    })
+}
+
+fn write_suite<W: Write>(out: &mut W, result: &FileResult) -> io::Result<()> {
+    let name = escape(&result.path.display().to_string());
+    let summary = &result.summary;
+    let failed_alone = failed_alone(result) as u64;
+    writeln!(
+        out,
+        r#"  <testsuite name="{}" tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+        name,
+        summary.tests + failed_alone,
+        summary.failed + failed_alone,
+        summary.skipped,
+        result.duration.as_secs_f64()
+    )?;
+    if !result.attempts.is_empty() {
+        writeln!(out, "    <properties>")?;
+        writeln!(
+            out,
+            r#"      <property name="attempts" value="{}"/>"#,
+            result.attempts.len() + 1
+        )?;
+        writeln!(
+            out,
+            r#"      <property name="flaky" value="{}"/>"#,
+            result.is_flaky()
+        )?;
+        writeln!(out, "    </properties>")?;
+    }
+    let tests = result
+        .document
+        .lines
+        .iter()
+        .enumerate()
+        .filter_map(|(line, test)| match test {
+            Line::Test(test) => Some((line, test)),
+            _ => None,
+        });
+    for (index, (line, test)) in tests.enumerate() {
+        // Unnumbered test points count up from 1 in order.
+        let number = test.number.unwrap_or(index as u64 + 1);
+        let mut test_name = number.to_string();
+        if let Some(description) = &test.description {
+            test_name = format!("{} - {}", test_name, description);
+        }
+        write!(
+            out,
+            r#"    <testcase classname="{}" name="{}""#,
+            name,
+            escape(&test_name)
+        )?;
+        if let Some(duration) = test.duration {
+            write!(out, r#" time="{:.3}""#, duration.as_secs_f64())?;
+        }
+        let reason = test
+            .directive
+            .as_ref()
+            .and_then(|directive| directive.reason.as_deref())
+            .unwrap_or("");
+        let stderr = result.stderr_for(line);
+        let outcome = Outcome::of(test);
+        let retried: Vec<_> = (1..)
+            .zip(&result.attempts)
+            .filter(|(_, attempt)| failed(attempt, number))
+            .map(|(attempt, _)| attempt)
+            .collect();
+        if stderr.is_empty()
+            && retried.is_empty()
+            && matches!(outcome, Outcome::Pass | Outcome::Todo)
+        {
+            writeln!(out, "/>")?;
+            continue;
+        }
+        writeln!(out, ">")?;
+        match outcome {
+            Outcome::Fail => {
+                let details = details(result, line);
+                if details.is_empty() {
+                    writeln!(out, r#"      <failure message="not ok"/>"#)?
+                } else {
+                    writeln!(
+                        out,
+                        r#"      <failure message="not ok">{}</failure>"#,
+                        escape(&details)
+                    )?
+                }
+            }
+            Outcome::Skip => writeln!(out, r#"      <skipped message="{}"/>"#, escape(reason))?,
+            Outcome::Pass | Outcome::Todo => {}
+        }
+        // Surefire's names for failures before a rerun.
+        let element = match outcome {
+            Outcome::Fail => "rerunFailure",
+            _ => "flakyFailure",
+        };
+        for attempt in retried {
+            writeln!(
+                out,
+                r#"      <{} message="not ok on attempt {}"/>"#,
+                element, attempt
+            )?;
+        }
+        write_stderr(out, "      ", &stderr)?;
+        writeln!(out, "    </testcase>")?;
+    }
+    if failed_alone > 0 {
+        writeln!(
+            out,
+            r#"    <testcase classname="{}" name="{}">"#,
+            name, name
+        )?;
+        writeln!(
+            out,
+            r#"      <failure message="{}"/>"#,
+            escape(&verdict(result))
+        )?;
+        writeln!(out, "    </testcase>")?;
+    }
+    // Stderr from before the first test point, like a compile error.
+    let stderr: String = result
+        .stderr
+        .iter()
+        .filter(|stderr| stderr.test.is_none())
+        .map(|stderr| stderr.text.as_str())
+        .collect();
+    write_stderr(out, "    ", &stderr)?;
+    writeln!(out, "  </testsuite>")
+}
+
+/// The comments and YAML blocks right after `document.lines[line]`, which
+/// say why it failed.
+fn details(result: &FileResult, line: usize) -> String {
+    result.document.lines[line + 1..]
+        .iter()
+        .take_while(|line| matches!(line, Line::Comment(_) | Line::Yaml(_)))
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+fn write_stderr<W: Write>(out: &mut W, indent: &str, stderr: &str) -> io::Result<()> {
+    if stderr.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "{}<system-err>{}</system-err>", indent, escape(stderr))
+}
+
+/// `text` made safe for an attribute or element, dropping the control
+/// characters XML 1.0 can't hold.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c < ' ' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::formatter::Quiet;
+    use crate::harness::tests::{flaky, script};
+    use crate::harness::{Harness, Stderr};
+
+    #[test]
+    fn test_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            script(
+                dir.path(),
+                "a.t",
+                "1..4\nok 1 - <one> & \"two\"\nnot ok 2\n# got: 1\n  ---\n  expected: 2\n  ...\n\
+                 ok 3 # SKIP no db\nnot ok 4 # TODO later\n",
+                0,
+            ),
+            script(dir.path(), "b.t", "1..2\nok 1\n", 0),
+        ];
+        let mut results = Harness::new()
+            .formatter(Quiet::new(io::sink()))
+            .run(&files)
+            .unwrap();
+        results[0].stderr = vec![
+            Stderr {
+                line: 0,
+                test: None,
+                text: "loading <lib>\n".to_string(),
+            },
+            Stderr {
+                line: 5,
+                test: Some(2),
+                text: "#   Failed test 2\n".to_string(),
+            },
+        ];
+
+        let mut out = Vec::new();
+        write(&mut out, &results).unwrap();
+        let xml = String::from_utf8(out).unwrap();
+        let a = files[0].display();
+        let b = files[1].display();
+        assert!(xml.starts_with(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites tests=\"6\" failures=\"2\" skipped=\"1\" time=\""
+        ));
+        assert!(xml.contains(&format!(
+            "<testsuite name=\"{}\" tests=\"4\" failures=\"1\" skipped=\"1\" time=\"",
+            a
+        )));
+        assert!(xml.contains(&format!(
+            "<testcase classname=\"{}\" name=\"1 - &lt;one&gt; &amp; &quot;two&quot;\" time=\"",
+            a
+        )));
+        assert!(xml.contains(
+            "\n      <failure message=\"not ok\"># got: 1\n  ---\n  expected: 2\n  ...\n</failure>\n      \
+             <system-err>#   Failed test 2\n</system-err>\n"
+        ));
+        assert!(
+            xml.contains("\n    <system-err>loading &lt;lib&gt;\n</system-err>\n  </testsuite>\n")
+        );
+        assert!(xml.contains("\n      <skipped message=\"no db\"/>\n"));
+        assert!(xml.contains(&format!(
+            "<testcase classname=\"{}\" name=\"{}\">\n      <failure message=\"Planned 2 but ran 1\"/>\n",
+            b, b
+        )));
+        assert!(xml.ends_with("  </testsuite>\n</testsuites>\n"));
+        assert_eq!(escape("a\u{1b}b\n"), "ab\n");
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            script(dir.path(), "failed.t", "1..2\nok 1\nnot ok 2\n", 1),
+            script(dir.path(), "died.t", "1..1\nok 1\n", 255),
+        ];
+        let results = Harness::new()
+            .formatter(Quiet::new(io::sink()))
+            .run(&files)
+            .unwrap();
+
+        let mut out = Vec::new();
+        write(&mut out, &results).unwrap();
+        let xml = String::from_utf8(out).unwrap();
+        assert!(xml.contains("<testsuites tests=\"4\" failures=\"2\" skipped=\"0\""));
+        assert!(xml.contains(&format!(
+            "<testsuite name=\"{}\" tests=\"2\" failures=\"1\" skipped=\"0\"",
+            files[0].display()
+        )));
+        assert!(!xml.contains(&format!("name=\"{0}\">", files[0].display())));
+        assert!(xml.contains(&format!(
+            "<testcase classname=\"{0}\" name=\"{0}\">",
+            files[1].display()
+        )));
+    }
+
+    #[test]
+    fn test_attempts() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            flaky(dir.path(), "once.t", 1),
+            flaky(dir.path(), "always.t", 5),
+        ];
+        let results = Harness::new()
+            .formatter(Quiet::new(io::sink()))
+            .retries(1)
+            .run(&files)
+            .unwrap();
+
+        let mut out = Vec::new();
+        write(&mut out, &results).unwrap();
+        let xml = String::from_utf8(out).unwrap();
+        assert!(xml.contains(
+            "    <properties>\n      <property name=\"attempts\" value=\"2\"/>\n      \
+             <property name=\"flaky\" value=\"true\"/>\n    </properties>\n"
+        ));
+        assert!(xml.contains(&format!(
+            "<testcase classname=\"{}\" name=\"1\" time=\"",
+            files[0].display()
+        )));
+        assert!(xml.contains(
+            "\">\n      <flakyFailure message=\"not ok on attempt 1\"/>\n    </testcase>\n"
+        ));
+        assert!(xml.contains(
+            "\">\n      <failure message=\"not ok\"/>\n      \
+             <rerunFailure message=\"not ok on attempt 1\"/>\n    </testcase>\n"
+        ));
+        assert!(xml.contains("<property name=\"flaky\" value=\"false\"/>"));
+    }
+}
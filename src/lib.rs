@@ -1,3 +1,26 @@
+pub mod aggregate;
+pub mod archive;
+pub mod baseline;
+pub mod compress;
+pub mod config;
+pub mod cst;
+pub mod diagnostic;
+pub mod diff;
+pub mod document;
+pub mod extract;
+pub mod formatter;
+pub mod gate;
+pub mod handler;
+pub mod harness;
+pub mod junit;
+pub mod limits;
+pub mod reporter;
+pub mod rewrite;
+pub mod shard;
+pub mod source;
+pub mod state;
+pub mod stream;
+pub mod summary;
 pub mod tap_parser;
 
 extern crate pest;
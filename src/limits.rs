@@ -0,0 +1,271 @@
+use std::fmt;
+use std::io;
+use std::process::{Command, ExitStatus};
+use std::time::Duration;
+
+use crate::harness::Stderr;
+
+/// Resource limits for each test program, applied with `setrlimit` before it
+/// starts. Only available on Linux.
+///
+/// ```
+/// use tap_pest::harness::Harness;
+/// use tap_pest::limits::Limits;
+///
+/// let harness = Harness::new().limits(
+///     Limits::new()
+///         .address_space(2 << 30)
+///         .cpu(60)
+///         .open_files(256),
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Bytes of virtual memory, `RLIMIT_AS`.
+    pub address_space: Option<u64>,
+    /// Seconds of CPU time, `RLIMIT_CPU`.
+    pub cpu: Option<u64>,
+    /// Open file descriptors, `RLIMIT_NOFILE`.
+    pub open_files: Option<u64>,
+}
+
+/// A limit a program ran into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    AddressSpace(u64),
+    Cpu(u64),
+    OpenFiles(u64),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::AddressSpace(bytes) => write!(f, "address space limit of {} bytes", bytes),
+            Limit::Cpu(seconds) => write!(f, "CPU time limit of {}s", seconds),
+            Limit::OpenFiles(count) => write!(f, "open files limit of {}", count),
+        }
+    }
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Limits::default()
+    }
+
+    pub fn address_space(mut self, bytes: u64) -> Self {
+        self.address_space = Some(bytes);
+        self
+    }
+
+    pub fn cpu(mut self, seconds: u64) -> Self {
+        self.cpu = Some(seconds);
+        self
+    }
+
+    pub fn open_files(mut self, count: u64) -> Self {
+        self.open_files = Some(count);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Limits::default()
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn apply(&self, command: &mut Command) -> io::Result<()> {
+        use std::os::unix::process::CommandExt;
+
+        if self.is_empty() {
+            return Ok(());
+        }
+        // The hard CPU limit is a second later, so the program gets SIGXCPU
+        // before SIGKILL.
+        let limits = [
+            (libc::RLIMIT_AS, self.address_space, 0),
+            (libc::RLIMIT_CPU, self.cpu, 1),
+            (libc::RLIMIT_NOFILE, self.open_files, 0),
+        ];
+        // SAFETY: only calls setrlimit, which is async-signal-safe, between
+        // fork and exec.
+        unsafe {
+            command.pre_exec(move || {
+                for &(resource, limit, grace) in &limits {
+                    if let Some(limit) = limit {
+                        let limit = libc::rlimit {
+                            rlim_cur: limit as libc::rlim_t,
+                            rlim_max: limit.saturating_add(grace) as libc::rlim_t,
+                        };
+                        if libc::setrlimit(resource, &limit) != 0 {
+                            return Err(io::Error::last_os_error());
+                        }
+                    }
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn apply(&self, _command: &mut Command) -> io::Result<()> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "resource limits are only supported on Linux",
+            ))
+        }
+    }
+
+    /// The limits a program probably ran into, judging by how it exited, the
+    /// `cpu` time it used, and what it said on stderr after its last line of
+    /// TAP, of which there were `lines`. A program killed for a timeout didn't
+    /// run into its CPU limit, nor did one killed by anything else before it
+    /// had used it up, and one that crashed without saying why just crashed.
+    pub(crate) fn exceeded(
+        &self,
+        status: &ExitStatus,
+        cpu: Option<Duration>,
+        stderr: &[Stderr],
+        lines: usize,
+        timed_out: bool,
+    ) -> Vec<Limit> {
+        let said = |messages: &[&str]| {
+            stderr
+                .iter()
+                .filter(|stderr| stderr.line >= lines)
+                .any(|stderr| messages.iter().any(|message| stderr.text.contains(message)))
+        };
+        let signal = signal(status);
+
+        let mut exceeded = Vec::new();
+        if let Some(bytes) = self.address_space {
+            if said(&[
+                "Out of memory",
+                "out of memory",
+                "memory allocation of",
+                "memory exhausted",
+                "Cannot allocate memory",
+            ]) {
+                exceeded.push(Limit::AddressSpace(bytes));
+            }
+        }
+        if let Some(seconds) = self.cpu {
+            let used_up = cpu.is_some_and(|cpu| cpu >= Duration::from_secs(seconds));
+            if signal == Some(SIGXCPU) || (signal == Some(SIGKILL) && used_up && !timed_out) {
+                exceeded.push(Limit::Cpu(seconds));
+            }
+        }
+        if let Some(count) = self.open_files {
+            if said(&["Too many open files"]) {
+                exceeded.push(Limit::OpenFiles(count));
+            }
+        }
+        exceeded
+    }
+}
+
+// Linux's numbers, which is the only place limits apply.
+const SIGKILL: i32 = 9;
+const SIGXCPU: i32 = 24;
+
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Parses a size like `512M` or `2G` into bytes.
+pub fn bytes(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let (number, unit) = match size.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => size.split_at(index),
+        None => (size, ""),
+    };
+    let scale: u64 = match unit.trim() {
+        "" => 1,
+        "K" | "k" => 1 << 10,
+        "M" | "m" => 1 << 20,
+        "G" | "g" => 1 << 30,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bytes() {
+        assert_eq!(bytes("512"), Some(512));
+        assert_eq!(bytes("4K"), Some(4096));
+        assert_eq!(bytes("512M"), Some(512 << 20));
+        assert_eq!(bytes(" 2G "), Some(2 << 30));
+        assert_eq!(bytes("2T"), None);
+        assert_eq!(bytes("lots"), None);
+        assert_eq!(bytes(""), None);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            Limit::AddressSpace(1024).to_string(),
+            "address space limit of 1024 bytes"
+        );
+        assert_eq!(Limit::Cpu(2).to_string(), "CPU time limit of 2s");
+        assert_eq!(Limit::OpenFiles(8).to_string(), "open files limit of 8");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exceeded() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let limits = Limits::new().address_space(1 << 20).open_files(8).cpu(1);
+        let stderr = |line: usize, text: &str| Stderr {
+            line,
+            test: None,
+            text: text.to_string(),
+        };
+        let exited = ExitStatus::from_raw(2 << 8);
+        let segv = ExitStatus::from_raw(11);
+
+        let kill = ExitStatus::from_raw(9);
+        let used_up = Some(Duration::from_secs(1));
+
+        assert!(limits.exceeded(&segv, used_up, &[], 2, false).is_empty());
+        for message in ["Out of memory!\n", "dd: memory exhausted by input buffer\n"] {
+            assert_eq!(
+                limits.exceeded(&exited, None, &[stderr(2, message)], 2, false),
+                [Limit::AddressSpace(1 << 20)]
+            );
+        }
+        // Said by the tests themselves, before the TAP ended.
+        let talk = [
+            stderr(1, "# Cannot allocate memory is expected\n"),
+            stderr(1, "# Too many open files\n"),
+        ];
+        assert!(limits.exceeded(&exited, None, &talk, 2, false).is_empty());
+        assert_eq!(
+            limits.exceeded(&ExitStatus::from_raw(24), None, &[], 2, false),
+            [Limit::Cpu(1)]
+        );
+        assert_eq!(
+            limits.exceeded(&kill, used_up, &[], 2, false),
+            [Limit::Cpu(1)]
+        );
+        // Killed by something else: the OOM killer, a person, or a timeout.
+        let early = Some(Duration::from_millis(200));
+        assert!(limits.exceeded(&kill, early, &[], 2, false).is_empty());
+        assert!(limits.exceeded(&kill, None, &[], 2, false).is_empty());
+        assert!(limits.exceeded(&kill, used_up, &[], 2, true).is_empty());
+    }
+}
@@ -0,0 +1,198 @@
+use std::env;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::process;
+
+use tap_pest::archive::Archive;
+use tap_pest::baseline::Baseline;
+use tap_pest::compress::decompress;
+use tap_pest::config::{Config, CONFIG_FILE};
+use tap_pest::diff::diff;
+use tap_pest::document::TapDocument;
+use tap_pest::junit;
+use tap_pest::shard::ShardBy;
+use tap_pest::state::{State, StateOptions};
+use tap_pest::summary::Summary;
+
+const STATE_FILE: &str = ".tap-pest-state";
+
+const USAGE: &str = "usage:
+    tap-pest diff <before.tap> <after.tap>
+        List test points that changed outcome, were added or were removed.
+        Exits with 1 if any test started failing.
+
+    tap-pest baseline <archive>
+        Print a baseline expecting the failures in a saved run.
+
+    tap-pest merge <dir> <archive>...
+        Combine the archives of sharded runs into one in <dir>.
+
+    tap-pest run [<flag>...] [<test>...]
+        Run test programs and report on their TAP, by default the ones
+        matching t/**/*.t. Perl, shell and Python tests run through their
+        interpreter, others by their #! line. Exits with 1 if any failed.
+        Settings come from tap-pest.toml, and these flags override it:
+            --config=<file>           read settings from <file> instead
+            --tests=<glob>            which tests to run, may be repeated
+            --jobs=<n>                run <n> tests at once
+            --timeout=<seconds>       kill tests that run longer
+            --formatter=<name>        dots, verbose, quiet or failures
+            --archive=<dir>           save the TAP of the run in <dir>
+            --junit=<file>            write the results to <file> as JUnit XML
+            --baseline=<file>         tests expected to fail, one per line as
+                                      <file> <number> or <file> - <description>;
+                                      only other failures exit with 1
+            --env=<name>=<value>      set an environment variable for tests
+            --interpreters=<ext>=<command>
+                                      run *.<ext> tests with <command>
+            --sandbox=true            run each test in a new temporary directory
+            --retries=<n>             rerun failing tests up to <n> times
+            --shard=<i>/<n>           run the <i>th of <n> disjoint parts of
+                                      the tests, split by --shard_by=hash or
+                                      by duration in .tap-pest-state, which
+                                      must not be saved by the run
+            --gates=<name>=<value>    pass CI despite failures as long as the
+                                      totals keep within max_failures or
+                                      min_pass_rate (0 to 1); or fail it for
+                                      forbid_todo_passed, forbid_skips or
+                                      require_plan (true or false)
+            --limits=<name>=<value>   limit address_space (e.g. 512M), cpu
+                                      seconds or open_files, on Linux
+            --state=<options>         keep results in .tap-pest-state; a
+                                      comma separated list of:
+                failed, passed, all, fresh  which tests to run
+                new, slow, fast             what order to run them in
+                save                        record this run
+
+Files may be gzip or zstd compressed when built with those features.";
+
+fn read(path: &str) -> TapDocument<'static> {
+    File::open(path)
+        .and_then(decompress)
+        .and_then(TapDocument::from_reader)
+        .unwrap_or_else(|err| fail(&format!("{}: {}", path, err)))
+}
+
+fn run(args: &[&str]) {
+    let config_file = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--config="))
+        .unwrap_or(CONFIG_FILE);
+    let mut config = Config::load(config_file).unwrap_or_else(|err| fail(&err.to_string()));
+    let mut options = StateOptions::default();
+    let mut files = Vec::new();
+    let mut tests_flag = false;
+    for arg in args {
+        match arg.strip_prefix("--").and_then(|flag| flag.split_once('=')) {
+            Some(("config", _)) => {}
+            // The first --tests replaces the config's globs, later ones add.
+            Some(("tests", glob)) if !tests_flag => {
+                tests_flag = true;
+                config.tests = vec![glob.to_string()];
+            }
+            Some(("state", value)) => {
+                options = value.parse().unwrap_or_else(|err: String| fail(&err))
+            }
+            Some((key, value)) => config.set(key, value).unwrap_or_else(|err| fail(&err)),
+            None if arg.starts_with('-') => fail(USAGE),
+            None => files.push(PathBuf::from(arg)),
+        }
+    }
+    if files.is_empty() {
+        files = config.files().unwrap_or_else(|err| fail(&err.to_string()));
+    }
+
+    if options.save && config.shard.is_some() && config.shard_by == ShardBy::Duration {
+        // Each machine would save its own timings, and then disagree.
+        fail(
+            "--state=save can't be used with shard_by=duration, \
+             which needs the same .tap-pest-state on every machine",
+        );
+    }
+    let mut state =
+        State::load(STATE_FILE).unwrap_or_else(|err| fail(&format!("{}: {}", STATE_FILE, err)));
+    let files = state.select(&config.shard(&files, &state), &options);
+    let results = config
+        .harness()
+        .run(&files)
+        .unwrap_or_else(|err| fail(&err.to_string()));
+    let ok = match &config.baseline {
+        Some(path) => {
+            let baseline = Baseline::load(path).unwrap_or_else(|err| fail(&err.to_string()));
+            let report = baseline.check(&results);
+            print!("{}", report);
+            report.is_ok()
+        }
+        // The budget covers failed tests, `check_files` everything else.
+        None if config.gates.allow_failures() => true,
+        None => results.iter().all(|result| result.is_ok()),
+    };
+    let total = Summary::total(results.iter().map(|result| &result.summary));
+    let mut tripped = config.gates.check(&total);
+    tripped.extend(config.gates.check_files(&results));
+    for gate in &tripped {
+        println!("Gate tripped: {}", gate);
+    }
+    let ok = ok && tripped.is_empty();
+    if options.save {
+        state.record(&results);
+        state
+            .save(STATE_FILE)
+            .unwrap_or_else(|err| fail(&format!("{}: {}", STATE_FILE, err)));
+    }
+    if let Some(path) = &config.junit {
+        let mut xml = Vec::new();
+        junit::write(&mut xml, &results)
+            .and_then(|_| fs::write(path, xml))
+            .unwrap_or_else(|err| fail(&format!("{}: {}", path.display(), err)));
+    }
+    if let Some(dir) = &config.archive {
+        fs::create_dir_all(dir)
+            .and_then(|_| Archive::new(results).write_dir(dir))
+            .unwrap_or_else(|err| fail(&format!("{}: {}", dir.display(), err)));
+    }
+    if !ok {
+        process::exit(1);
+    }
+}
+
+fn merge(dir: &str, archives: &[&str]) {
+    let archives = archives.iter().map(|archive| {
+        Archive::read_dir(archive).unwrap_or_else(|err| fail(&format!("{}: {}", archive, err)))
+    });
+    fs::create_dir_all(dir)
+        .and_then(|_| Archive::merge(archives.collect::<Vec<_>>()))
+        .and_then(|archive| archive.write_dir(dir))
+        .unwrap_or_else(|err| fail(&format!("{}: {}", dir, err)));
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("tap-pest: {}", message);
+    process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["diff", before, after] => {
+            let diff = diff(&read(before), &read(after));
+            print!("{}", diff);
+            if diff.has_regressions() {
+                process::exit(1);
+            }
+        }
+        ["baseline", archive] => {
+            let archive = Archive::read_dir(archive)
+                .unwrap_or_else(|err| fail(&format!("{}: {}", archive, err)));
+            print!("{}", Baseline::from_results(&archive.results));
+        }
+        ["merge", dir, archives @ ..] if !archives.is_empty() => merge(dir, archives),
+        ["run", args @ ..] => run(args),
+        _ => fail(USAGE),
+    }
+}
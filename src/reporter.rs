@@ -0,0 +1,299 @@
+use std::io::{self, IsTerminal, Write};
+
+use crate::document::{Bailout, Comment, Directive, Plan, TapDocument, TestPoint, Yaml};
+use crate::handler::TapHandler;
+use crate::summary::Summary;
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders TAP for people: one marked line per test point, the failing tests
+/// again at the end with the comments and YAML that followed them, then the
+/// totals.
+///
+/// Works on a finished document through `report`, or on a live stream by
+/// passing the reporter to `StreamParser::drive` and calling `finish`.
+pub struct Reporter<W> {
+    out: W,
+    color: bool,
+    summary: Summary,
+    failures: Vec<Failure>,
+    /// Whether comments and YAML belong to the last failure.
+    in_failure: bool,
+    error: Option<io::Error>,
+}
+
+struct Failure {
+    title: String,
+    details: Vec<String>,
+}
+
+impl Reporter<io::Stdout> {
+    /// Reports to stdout, with color only when it's a terminal.
+    pub fn stdout() -> Self {
+        let out = io::stdout();
+        let color = out.is_terminal();
+        Reporter::new(out, color)
+    }
+}
+
+impl<W: Write> Reporter<W> {
+    pub fn new(out: W, color: bool) -> Self {
+        Reporter {
+            out,
+            color,
+            summary: Summary::default(),
+            failures: Vec::new(),
+            in_failure: false,
+            error: None,
+        }
+    }
+
+    pub fn report(mut self, document: &TapDocument) -> io::Result<W> {
+        document.visit(&mut self);
+        self.finish()
+    }
+
+    pub fn summary(&self) -> &Summary {
+        &self.summary
+    }
+
+    /// Returns the writer, or the first error writing to it.
+    pub fn finish(self) -> io::Result<W> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.out),
+        }
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.error.is_none() {
+            if let Err(err) = writeln!(self.out, "{}", line) {
+                self.error = Some(err);
+            }
+        }
+    }
+
+    fn write_totals(&mut self) {
+        let summary = &self.summary;
+        let mut totals = format!(
+            "{} test{}, {} passed, {} failed",
+            summary.tests,
+            if summary.tests == 1 { "" } else { "s" },
+            summary.passed,
+            summary.failed
+        );
+        if summary.skipped > 0 {
+            totals += &format!(", {} skipped", summary.skipped);
+        }
+        if summary.todo > 0 {
+            totals += &format!(", {} todo", summary.todo);
+        }
+        if summary.todo_passed > 0 {
+            totals += &format!(" ({} unexpectedly passed)", summary.todo_passed);
+        }
+        match summary.planned {
+            Some(planned) if planned != summary.tests => {
+                totals += &format!(", planned {}", planned)
+            }
+            None => totals += ", no plan",
+            _ => {}
+        }
+
+        let color = if summary.is_ok() { GREEN } else { RED };
+        let totals = self.paint(color, &totals);
+        self.write_line(&totals);
+    }
+}
+
+fn test_title(test: &TestPoint) -> String {
+    let mut title = String::new();
+    if let Some(number) = test.number {
+        title += &number.to_string();
+    }
+    if let Some(description) = &test.description {
+        if !title.is_empty() {
+            title += " - ";
+        }
+        title += description;
+    }
+    title
+}
+
+fn directive_text(directive: &Directive) -> String {
+    directive.to_string().trim_end().to_string()
+}
+
+impl<W: Write> TapHandler for Reporter<W> {
+    fn on_plan(&mut self, plan: &Plan) {
+        self.summary.on_plan(plan);
+
+        if let Some(directive) = &plan.directive {
+            let line = self.paint(
+                YELLOW,
+                &format!("skipped all {}", directive_text(directive)),
+            );
+            self.write_line(&line);
+        }
+    }
+
+    fn on_test(&mut self, test: &TestPoint) {
+        self.summary.on_test(test);
+
+        let title = test_title(test);
+        let line = match &test.directive {
+            Some(directive) => {
+                let marker = if test.ok { "✓" } else { "○" };
+                self.paint(
+                    YELLOW,
+                    &format!("{} {} {}", marker, title, directive_text(directive)),
+                )
+            }
+            None if test.ok => self.paint(GREEN, &format!("✓ {}", title)),
+            None => self.paint(RED, &format!("✗ {}", title)),
+        };
+        self.write_line(&line);
+
+        self.in_failure = !test.ok && test.directive.is_none();
+        if self.in_failure {
+            self.failures.push(Failure {
+                title,
+                details: Vec::new(),
+            });
+        }
+    }
+
+    fn on_comment(&mut self, comment: &Comment) {
+        if self.in_failure {
+            if let Some(failure) = self.failures.last_mut() {
+                let detail = match &comment.text {
+                    Some(text) if !text.is_empty() => format!("# {}", text),
+                    _ => "#".to_string(),
+                };
+                failure.details.push(detail);
+            }
+        }
+    }
+
+    fn on_yaml(&mut self, yaml: &Yaml) {
+        if self.in_failure {
+            if let Some(failure) = self.failures.last_mut() {
+                failure
+                    .details
+                    .extend(yaml.text.lines().map(|line| line.trim_end().to_string()));
+            }
+        }
+    }
+
+    fn on_bailout(&mut self, bailout: &Bailout) {
+        self.summary.on_bailout(bailout);
+        self.in_failure = false;
+
+        let line = match &bailout.reason {
+            Some(reason) => format!("Bail out! {}", reason),
+            None => "Bail out!".to_string(),
+        };
+        let line = self.paint(RED, &line);
+        self.write_line(&line);
+    }
+
+    fn on_end(&mut self) {
+        if !self.failures.is_empty() {
+            let failures = std::mem::take(&mut self.failures);
+            self.write_line("");
+            let header = self.paint(RED, "Failures:");
+            self.write_line(&header);
+            for failure in &failures {
+                let title = self.paint(RED, &format!("  ✗ {}", failure.title));
+                self.write_line(&title);
+                for detail in &failure.details {
+                    self.write_line(&format!("      {}", detail));
+                }
+            }
+            self.failures = failures;
+        }
+
+        self.write_line("");
+        self.write_totals();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::StreamParser;
+
+    const EXAMPLE: &str = r"1..5
+ok 1 - fine
+not ok 2 - broken
+# got 1
+#
+  ---
+  expected: 2
+  ...
+not ok 3 - later # TODO not written
+ok 4 # SKIP no network
+# not a failure detail
+not ok 5
+";
+
+    #[test]
+    fn test_report_document() {
+        let document = TapDocument::parse(EXAMPLE).unwrap();
+        let out = Reporter::new(Vec::new(), false).report(&document).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r"✓ 1 - fine
+✗ 2 - broken
+○ 3 - later # TODO not written
+✓ 4 # SKIP no network
+✗ 5
+
+Failures:
+  ✗ 2 - broken
+      # got 1
+      #
+        expected: 2
+  ✗ 5
+
+5 tests, 3 passed, 2 failed, 1 skipped, 1 todo
+"
+        );
+    }
+
+    #[test]
+    fn test_report_stream() {
+        let mut reporter = Reporter::new(Vec::new(), true);
+        StreamParser::new("1..0 # SKIP no database\n".as_bytes())
+            .drive(&mut reporter)
+            .unwrap();
+
+        assert_eq!(reporter.summary().skip_all.as_deref(), Some("no database"));
+        assert_eq!(
+            String::from_utf8(reporter.finish().unwrap()).unwrap(),
+            "\x1b[33mskipped all # SKIP no database\x1b[0m\n\n\x1b[32m0 tests, 0 passed, 0 failed\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn test_report_bailout() {
+        let document = TapDocument::parse("1..3\nok 1\nBail out! no database\n").unwrap();
+        let out = Reporter::new(Vec::new(), false).report(&document).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "✓ 1\nBail out! no database\n\n1 test, 1 passed, 0 failed, planned 3\n"
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use std::borrow::Cow;
+
+use crate::document::{parse_stream_line, Line, Plan, Span, TapDocument};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlanPosition {
+    Top,
+    Bottom,
+}
+
+/// Fixes up concatenated or hand-edited TAP.
+///
+/// Each transformation is off until asked for. Rewritten documents are meant
+/// to be written out with `Display`, which always uses the canonical
+/// `ok N - description # TODO reason` separators; spans still point into the
+/// original input.
+///
+/// ```
+/// use tap_pest::document::TapDocument;
+/// use tap_pest::rewrite::{PlanPosition, Rewrite};
+///
+/// let input = "1..1\nok 1 - a\n1..2\nok 1 - b\nok 2 - c\n";
+/// let document = TapDocument::parse(input).unwrap();
+/// let rewritten = Rewrite::new()
+///     .renumber(true)
+///     .plan(PlanPosition::Top)
+///     .apply(&document);
+/// assert_eq!(rewritten.to_string(), "1..3\nok 1 - a\nok 2 - b\nok 3 - c\n");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Rewrite {
+    renumber: bool,
+    plan: Option<PlanPosition>,
+    normalize: bool,
+    comments: bool,
+}
+
+impl Default for Rewrite {
+    fn default() -> Self {
+        Rewrite::new()
+    }
+}
+
+impl Rewrite {
+    pub fn new() -> Self {
+        Rewrite {
+            renumber: false,
+            plan: None,
+            normalize: false,
+            comments: true,
+        }
+    }
+
+    /// Number test points 1, 2, 3, ... in order.
+    pub fn renumber(mut self, renumber: bool) -> Self {
+        self.renumber = renumber;
+        self
+    }
+
+    /// Replace every plan with one counting the test points, at the top (after
+    /// any version line) or the bottom. A skip-all plan stays at the top, the
+    /// only place it can go.
+    ///
+    /// Plans and version lines the parser saw as unknown lines, as happens
+    /// when streams are concatenated, are dropped too.
+    pub fn plan(mut self, position: PlanPosition) -> Self {
+        self.plan = Some(position);
+        self
+    }
+
+    /// Trim the whitespace around descriptions and directive reasons.
+    pub fn normalize(mut self, normalize: bool) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    /// Keep comment lines, which is the default.
+    pub fn comments(mut self, keep: bool) -> Self {
+        self.comments = keep;
+        self
+    }
+
+    /// The rewritten document. Written out, it parses again with
+    /// `TapDocument::parse` whether `document` came from there or from
+    /// `StreamParser`, with or without a plan position.
+    pub fn apply<'a>(&self, document: &TapDocument<'a>) -> TapDocument<'a> {
+        let mut lines = Vec::new();
+        let mut old_plan = None;
+        let mut tests = 0;
+        for line in &document.lines {
+            match line {
+                Line::Plan(plan) if self.plan.is_some() => {
+                    old_plan.get_or_insert(plan);
+                    continue;
+                }
+                Line::Unknown(unknown) if self.plan.is_some() && is_header(&unknown.text) => {
+                    continue;
+                }
+                // TAP only allows a version on the first line.
+                Line::Version(_) if !lines.is_empty() => continue,
+                Line::Comment(_) if !self.comments => continue,
+                _ => {}
+            }
+
+            let mut line = line.clone();
+            match &mut line {
+                Line::Test(test) => {
+                    tests += 1;
+                    if self.renumber {
+                        test.number = Some(tests);
+                    }
+                    if self.normalize {
+                        test.description = test.description.take().and_then(trim);
+                        if let Some(directive) = &mut test.directive {
+                            directive.reason = directive.reason.take().and_then(trim);
+                        }
+                    }
+                }
+                Line::Plan(Plan {
+                    directive: Some(directive),
+                    ..
+                }) if self.normalize => {
+                    directive.reason = directive.reason.take().and_then(trim);
+                }
+                _ => {}
+            }
+            lines.push(line);
+        }
+
+        if let Some(position) = self.plan {
+            let mut directive = old_plan.and_then(|plan| plan.directive.clone());
+            if tests > 0 {
+                directive = None;
+            } else if let Some(directive) = &mut directive {
+                if self.normalize {
+                    directive.reason = directive.reason.take().and_then(trim);
+                }
+            }
+
+            let top = position == PlanPosition::Top || directive.is_some();
+            let plan = Line::Plan(Plan {
+                count: tests,
+                directive,
+                span: Span::default(),
+            });
+            if !top {
+                lines.push(plan);
+            } else if let Some(Line::Version(_)) = lines.first() {
+                lines.insert(1, plan);
+            } else {
+                lines.insert(0, plan);
+            }
+        }
+
+        TapDocument { lines }
+    }
+}
+
+/// A plan or version line that ended up as an unknown line.
+fn is_header(text: &str) -> bool {
+    let line = format!("{}\n", text);
+    matches!(
+        parse_stream_line(&line, 0, 1).as_deref(),
+        Ok([Line::Plan(_)]) | Ok([Line::Version(_)])
+    )
+}
+
+fn trim(text: Cow<str>) -> Option<Cow<str>> {
+    let trimmed = match text {
+        Cow::Borrowed(text) => Cow::Borrowed(text.trim()),
+        Cow::Owned(text) => Cow::Owned(text.trim().to_string()),
+    };
+    if trimmed.is_empty() {
+        None
+    } else {
+        Some(trimmed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(rewrite: Rewrite, input: &str) -> String {
+        let document = TapDocument::parse(input).unwrap();
+        let output = rewrite.apply(&document).to_string();
+        assert!(
+            TapDocument::parse(&output).is_ok(),
+            "doesn't reparse:\n{}",
+            output
+        );
+        output
+    }
+
+    #[test]
+    fn test_concatenated() {
+        let input = "TAP version 13\n1..2\nok 1 - a\nnot ok 2 - b\n# failed\nTAP version 13\n1..1\nok 1 - c\n";
+
+        assert_eq!(
+            rewrite(Rewrite::new().renumber(true).plan(PlanPosition::Top), input),
+            "TAP version 13\n1..3\nok 1 - a\nnot ok 2 - b\n# failed\nok 3 - c\n"
+        );
+        assert_eq!(
+            rewrite(
+                Rewrite::new()
+                    .renumber(true)
+                    .plan(PlanPosition::Bottom)
+                    .comments(false),
+                input
+            ),
+            "TAP version 13\nok 1 - a\nnot ok 2 - b\nok 3 - c\n1..3\n"
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let input = "ok 1 -   spaced out   # todo   later  \nok 2 - fine # SKIP \n#  comment kept as is\n1..2\n";
+
+        assert_eq!(
+            rewrite(Rewrite::new(), input),
+            "ok 1 -   spaced out # TODO   later  \nok 2 - fine # SKIP\n#  comment kept as is\n1..2\n"
+        );
+        assert_eq!(
+            rewrite(Rewrite::new().normalize(true), input),
+            "ok 1 - spaced out # TODO later\nok 2 - fine # SKIP\n#  comment kept as is\n1..2\n"
+        );
+    }
+
+    #[test]
+    fn test_streamed() {
+        // StreamParser takes lines the document grammar would place
+        // differently; written out without a new plan they still parse.
+        let inputs = [
+            "ok 1\n1..2\nok 2\n",
+            "1..1\n1..1\nok 1\n",
+            "ok 1\n1..0 # SKIP x\n",
+            "ok 1\n  ---\n  not closed\nnot ok 2",
+            "Bail out!\n1..1\n",
+        ];
+        for input in inputs.iter() {
+            let document = TapDocument::from_reader(input.as_bytes()).unwrap();
+            for rewrite in &[
+                Rewrite::new(),
+                Rewrite::new().renumber(true).normalize(true),
+            ] {
+                let output = rewrite.apply(&document).to_string();
+                assert!(
+                    TapDocument::parse(&output).is_ok(),
+                    "doesn't reparse:\n{}",
+                    output
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_skip_all_plan() {
+        let input = "# nothing to do\n1..0 # SKIP no database\n";
+
+        assert_eq!(
+            rewrite(Rewrite::new().plan(PlanPosition::Bottom), input),
+            "1..0 # SKIP no database\n# nothing to do\n"
+        );
+    }
+}
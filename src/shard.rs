@@ -0,0 +1,220 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::state::State;
+
+/// One of `count` disjoint parts of a test suite, for spreading it across
+/// machines. `index` counts from 1, as in `--shard=2/4`.
+///
+/// Every machine must be given the same files, and for `balance` the same
+/// state file, to agree on the parts. A run that saves its state changes the
+/// file, so it can't be balanced.
+///
+/// ```
+/// use tap_pest::shard::Shard;
+///
+/// let files = ["t/a.t", "t/b.t", "t/c.t"];
+/// let first: Shard = "1/2".parse().unwrap();
+/// let second: Shard = "2/2".parse().unwrap();
+/// assert_eq!(first.select(&files).len() + second.select(&files).len(), 3);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shard {
+    pub index: usize,
+    pub count: usize,
+}
+
+/// How a `Shard` splits the files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShardBy {
+    /// By a hash of each file's path, which keeps a file in the same shard as
+    /// others come and go.
+    #[default]
+    Hash,
+    /// By how long each file took in the state file, so shards take about as
+    /// long as each other.
+    Duration,
+}
+
+impl Shard {
+    /// The files in this shard, picked by a stable hash of their paths, in
+    /// their original order.
+    pub fn select<P: AsRef<Path>>(&self, files: &[P]) -> Vec<PathBuf> {
+        files
+            .iter()
+            .map(|file| file.as_ref().to_path_buf())
+            .filter(|file| self.owns(hash(file)))
+            .collect()
+    }
+
+    /// The files in this shard, dealt out slowest first to whichever shard
+    /// has the least to do. Files the state doesn't know are taken to be
+    /// average. Without any timings this is `select`.
+    pub fn balance<P: AsRef<Path>>(&self, files: &[P], state: &State) -> Vec<PathBuf> {
+        let elapsed = |file: &Path| state.files.get(file).map(|state| state.elapsed);
+        let known: Vec<Duration> = files
+            .iter()
+            .filter_map(|file| elapsed(file.as_ref()))
+            .collect();
+        if known.is_empty() {
+            return self.select(files);
+        }
+        let total = known.iter().fold(Duration::ZERO, |total, elapsed| {
+            total.saturating_add(*elapsed)
+        });
+        let average = total / known.len() as u32;
+
+        let mut slowest: Vec<(Duration, &Path, usize)> = files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| {
+                let file = file.as_ref();
+                (elapsed(file).unwrap_or(average), file, index)
+            })
+            .collect();
+        slowest.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+
+        let mut loads = vec![Duration::ZERO; self.count];
+        let mut mine = vec![false; files.len()];
+        for (elapsed, _, index) in slowest {
+            let (shard, load) = loads
+                .iter_mut()
+                .enumerate()
+                .min_by_key(|(_, load)| **load)
+                .expect("a shard");
+            *load = load.saturating_add(elapsed);
+            mine[index] = shard + 1 == self.index;
+        }
+        files
+            .iter()
+            .zip(mine)
+            .filter(|(_, mine)| *mine)
+            .map(|(file, _)| file.as_ref().to_path_buf())
+            .collect()
+    }
+
+    fn owns(&self, hash: u64) -> bool {
+        hash % self.count as u64 == (self.index - 1) as u64
+    }
+}
+
+/// Parses `i/n`, with `i` from 1 to `n`.
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(shard: &str) -> Result<Self, Self::Err> {
+        let error = || format!("shard needs i/n with i from 1 to n, not {:?}", shard);
+        let (index, count) = shard.trim().split_once('/').ok_or_else(error)?;
+        let index: usize = index.parse().map_err(|_| error())?;
+        let count: usize = count.parse().map_err(|_| error())?;
+        if index == 0 || index > count {
+            return Err(error());
+        }
+        Ok(Shard { index, count })
+    }
+}
+
+impl FromStr for ShardBy {
+    type Err = String;
+
+    fn from_str(by: &str) -> Result<Self, Self::Err> {
+        match by {
+            "hash" => Ok(ShardBy::Hash),
+            "duration" => Ok(ShardBy::Duration),
+            _ => Err(format!("shard_by needs hash or duration, not {:?}", by)),
+        }
+    }
+}
+
+/// FNV-1a of the path with `/` separators, which unlike `DefaultHasher`
+/// gives the same answer on every machine and Rust version.
+fn hash(path: &Path) -> u64 {
+    let path = path.to_string_lossy().replace('\\', "/");
+    path.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::FileState;
+
+    fn shards(count: usize) -> Vec<Shard> {
+        (1..=count).map(|index| Shard { index, count }).collect()
+    }
+
+    fn files() -> Vec<PathBuf> {
+        (0..20)
+            .map(|n| PathBuf::from(format!("t/{:02}.t", n)))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("2/4".parse(), Ok(Shard { index: 2, count: 4 }));
+        assert_eq!(" 1/1 ".parse(), Ok(Shard { index: 1, count: 1 }));
+        assert!("0/4".parse::<Shard>().is_err());
+        assert!("5/4".parse::<Shard>().is_err());
+        assert!("2".parse::<Shard>().is_err());
+        assert!("a/b".parse::<Shard>().is_err());
+        assert_eq!("duration".parse(), Ok(ShardBy::Duration));
+        assert!("time".parse::<ShardBy>().is_err());
+    }
+
+    #[test]
+    fn test_select() {
+        let files = files();
+        let parts: Vec<_> = shards(3).iter().map(|shard| shard.select(&files)).collect();
+
+        let mut all: Vec<_> = parts.concat();
+        all.sort();
+        assert_eq!(all, files);
+        assert!(parts.iter().all(|part| !part.is_empty()));
+        assert_eq!(parts[1], shards(3)[1].select(&files));
+        assert_eq!(hash(Path::new("t/a.t")), hash(Path::new("t\\a.t")));
+        assert_eq!(shards(1)[0].select(&files), files);
+    }
+
+    #[test]
+    fn test_balance() {
+        let files = files();
+        let mut state = State::default();
+        for (n, file) in files.iter().enumerate().take(15) {
+            let elapsed = Duration::from_secs(if n == 0 { 30 } else { 1 });
+            state.files.insert(
+                file.clone(),
+                FileState {
+                    elapsed,
+                    ..FileState::default()
+                },
+            );
+        }
+
+        let parts: Vec<_> = shards(2)
+            .iter()
+            .map(|shard| shard.balance(&files, &state))
+            .collect();
+        let mut all: Vec<_> = parts.concat();
+        all.sort();
+        assert_eq!(all, files);
+        // The 30s file gets a shard to itself.
+        assert_eq!(parts[0], [PathBuf::from("t/00.t")]);
+        assert_eq!(parts[1].len(), 19);
+
+        assert_eq!(
+            shards(2)[0].balance(&files, &State::default()),
+            shards(2)[0].select(&files)
+        );
+
+        for file in &files[..2] {
+            state.files.get_mut(file).unwrap().elapsed = Duration::MAX;
+        }
+        let parts: Vec<_> = shards(2)
+            .iter()
+            .map(|shard| shard.balance(&files, &state))
+            .collect();
+        assert_eq!(parts.concat().len(), files.len());
+    }
+}
@@ -0,0 +1,350 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// What a `SourceHandler` gets to look at when deciding whether it can run a
+/// test file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Source {
+    pub path: PathBuf,
+    /// The `#!` line, without the `#!`, if the file starts with one.
+    pub shebang: Option<String>,
+    pub executable: bool,
+}
+
+impl Source {
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Source> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let executable = is_executable(&file)?;
+
+        let mut first = Vec::new();
+        BufReader::new(file.take(1024)).read_until(b'\n', &mut first)?;
+        let shebang = first
+            .strip_prefix(b"#!")
+            .map(|line| String::from_utf8_lossy(line).trim().to_string());
+
+        Ok(Source {
+            path: path.to_path_buf(),
+            shebang,
+            executable,
+        })
+    }
+
+    pub fn extension(&self) -> Option<&str> {
+        self.path
+            .extension()
+            .and_then(|extension| extension.to_str())
+    }
+}
+
+/// Knows how to run one kind of test file, in the spirit of Perl's
+/// `TAP::Parser::SourceHandler`. `Sources` asks every handler to vote on a
+/// file and runs it with whichever is most confident.
+///
+/// Handlers are shared between the harness's jobs, so must be `Send` and
+/// `Sync`.
+pub trait SourceHandler: Send + Sync {
+    /// How sure the handler is that it can run `source`, from 0 (not at all)
+    /// to 1 (certain).
+    fn can_handle(&self, source: &Source) -> f32;
+
+    /// The command that runs `source`, with its TAP on stdout.
+    fn command(&self, source: &Source) -> Command;
+}
+
+/// Runs files with a given extension through an interpreter, like `perl` for
+/// `.t` files.
+#[derive(Clone, Debug)]
+pub struct Interpreter {
+    program: OsString,
+    args: Vec<OsString>,
+    extensions: Vec<String>,
+    vote: f32,
+}
+
+impl Interpreter {
+    pub fn new<S: Into<OsString>>(program: S) -> Self {
+        Interpreter {
+            program: program.into(),
+            args: Vec::new(),
+            extensions: Vec::new(),
+            vote: 0.8,
+        }
+    }
+
+    /// Passed to the interpreter before the test file.
+    pub fn arg<S: Into<OsString>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Without the leading dot.
+    pub fn extension<S: Into<String>>(mut self, extension: S) -> Self {
+        self.extensions.push(extension.into());
+        self
+    }
+
+    /// How sure it is about files with its extensions, 0.8 unless set, which
+    /// leaves a `#!` line to win.
+    pub fn vote(mut self, vote: f32) -> Self {
+        self.vote = vote;
+        self
+    }
+}
+
+impl SourceHandler for Interpreter {
+    fn can_handle(&self, source: &Source) -> f32 {
+        let extension = source.extension();
+        if self
+            .extensions
+            .iter()
+            .any(|known| Some(known.as_str()) == extension)
+        {
+            self.vote
+        } else {
+            0.0
+        }
+    }
+
+    fn command(&self, source: &Source) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args).arg(&source.path);
+        command
+    }
+}
+
+/// Runs a script with the interpreter named on its `#!` line, so scripts
+/// work even without the executable bit. A `#!` line beats the extension.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Shebang;
+
+impl SourceHandler for Shebang {
+    fn can_handle(&self, source: &Source) -> f32 {
+        if source.shebang.is_some() {
+            0.9
+        } else {
+            0.0
+        }
+    }
+
+    fn command(&self, source: &Source) -> Command {
+        let shebang = source.shebang.as_deref().unwrap_or_default();
+        let mut words = shebang.split_whitespace();
+        let mut command = Command::new(words.next().unwrap_or_default());
+        command.args(words).arg(&source.path);
+        command
+    }
+}
+
+/// Runs the file itself, for compiled test binaries.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Executable;
+
+impl SourceHandler for Executable {
+    fn can_handle(&self, source: &Source) -> f32 {
+        if source.executable {
+            0.5
+        } else {
+            0.0
+        }
+    }
+
+    fn command(&self, source: &Source) -> Command {
+        Command::new(&source.path)
+    }
+}
+
+/// The registry of `SourceHandler`s the harness picks from.
+///
+/// `Sources::new()` knows Perl (`.t`, `.pl`), shell (`.sh`) and Python
+/// (`.py`) tests, `#!` scripts and executables. The interpreter named on a
+/// script's `#!` line wins over the one for its extension.
+///
+/// ```
+/// use tap_pest::source::{Interpreter, Sources};
+///
+/// let sources = Sources::new().handler(Interpreter::new("node").extension("js"));
+/// ```
+pub struct Sources {
+    handlers: Vec<Box<dyn SourceHandler>>,
+}
+
+impl Default for Sources {
+    fn default() -> Self {
+        Sources::new()
+    }
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Sources::empty()
+            .handler(Interpreter::new("perl").extension("t").extension("pl"))
+            .handler(Interpreter::new("sh").extension("sh"))
+            .handler(Interpreter::new("python3").extension("py"))
+            .handler(Shebang)
+            .handler(Executable)
+    }
+
+    /// A registry with no handlers at all.
+    pub fn empty() -> Self {
+        Sources {
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Adds a handler. When two are equally sure, the one added later wins.
+    pub fn handler<H: SourceHandler + 'static>(mut self, handler: H) -> Self {
+        self.handlers.push(Box::new(handler));
+        self
+    }
+
+    /// The command that runs the test at `path`.
+    pub fn command(&self, path: &Path) -> io::Result<Command> {
+        let source = Source::new(path)?;
+        let mut best: Option<(f32, &dyn SourceHandler)> = None;
+        for handler in &self.handlers {
+            let vote = handler.can_handle(&source);
+            if vote > 0.0 && best.is_none_or(|(best, _)| vote >= best) {
+                best = Some((vote, handler.as_ref()));
+            }
+        }
+
+        match best {
+            Some((_, handler)) => Ok(handler.command(&source)),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: don't know how to run this test", path.display()),
+            )),
+        }
+    }
+}
+
+/// The test files matching any of `patterns`, sorted and without duplicates.
+/// Patterns are globs like `t/**/*.t`.
+pub fn discover<S: AsRef<str>>(patterns: &[S]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for pattern in patterns {
+        let paths = glob::glob(pattern.as_ref())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        for path in paths {
+            let path = path.map_err(io::Error::from)?;
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// The glob `discover` uses when none are configured.
+pub const DEFAULT_PATTERN: &str = "t/**/*.t";
+
+#[cfg(unix)]
+fn is_executable(file: &File) -> io::Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = file.metadata()?;
+    Ok(metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(file: &File) -> io::Result<bool> {
+    Ok(file.metadata()?.is_file())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn program(command: &Command) -> Vec<String> {
+        std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|part| part.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, text: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, text).unwrap();
+            path
+        };
+        let perl = write("perl.t", "use Test::More;\n");
+        let shell = write("shell.t", "#!/bin/sh -e\necho ok\n");
+        let python = write("python", "#!/usr/bin/env python3\nprint('ok')\n");
+        let module = write("module.py", "print('ok')\n");
+        let plain = write("plain", "ok\n");
+        let binary = dir.path().join("binary");
+        fs::copy("/bin/true", &binary).unwrap();
+
+        let sources = Sources::new();
+        let command = |path: &Path| program(&sources.command(path).unwrap());
+        let display = |path: &PathBuf| path.to_string_lossy().into_owned();
+        assert_eq!(command(&perl), ["perl", &display(&perl)]);
+        assert_eq!(command(&shell), ["/bin/sh", "-e", &display(&shell)]);
+        assert_eq!(
+            command(&python),
+            ["/usr/bin/env", "python3", &display(&python)]
+        );
+        assert_eq!(command(&module), ["python3", &display(&module)]);
+        assert_eq!(command(&binary), [display(&binary)]);
+        assert_eq!(
+            sources.command(&plain).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            sources
+                .command(&dir.path().join("missing"))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+
+        let sources = Sources::new().handler(Interpreter::new("cat").arg("-n").extension("t"));
+        assert_eq!(
+            program(&sources.command(&perl).unwrap()),
+            ["cat", "-n", &display(&perl)]
+        );
+    }
+
+    #[test]
+    fn test_discover() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("t/unit/deep")).unwrap();
+        for name in &[
+            "t/b.t",
+            "t/a.t",
+            "t/unit/deep/c.t",
+            "t/helper.pm",
+            "t/unit/d.sh",
+        ] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        let root = dir.path().to_string_lossy();
+
+        let files = discover(&[format!("{}/{}", root, DEFAULT_PATTERN)]).unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|file| file.strip_prefix(dir.path()).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            names,
+            ["t/a.t", "t/b.t", "t/unit/deep/c.t"]
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        );
+
+        let both = discover(&[format!("{}/t/*.t", root), format!("{}/t/**/*.sh", root)]).unwrap();
+        assert_eq!(both.len(), 3);
+        assert!(discover(&["t/[".to_string()]).is_err());
+    }
+}
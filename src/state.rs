@@ -0,0 +1,345 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use crate::archive::{duration, number, quote, seconds, time, unquote};
+use crate::harness::FileResult;
+
+/// Which files to run and in what order, as in `prove --state=failed,slow,save`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StateOptions {
+    /// Only files that failed last time they ran.
+    pub failed: bool,
+    /// Only files that passed last time they ran.
+    pub passed: bool,
+    /// Every file, which is what happens without `failed`, `passed` or `fresh`.
+    pub all: bool,
+    /// Only files that are new or were modified since they last ran.
+    pub fresh: bool,
+    /// Most recently modified files first.
+    pub new: bool,
+    /// Slowest files first.
+    pub slow: bool,
+    /// Fastest files first.
+    pub fast: bool,
+    /// Record this run in the state file.
+    pub save: bool,
+}
+
+impl StateOptions {
+    fn selects(&self) -> bool {
+        self.failed || self.passed || self.all || self.fresh
+    }
+}
+
+/// Parses a comma separated list like `failed,slow,save`.
+impl FromStr for StateOptions {
+    type Err = String;
+
+    fn from_str(options: &str) -> Result<Self, Self::Err> {
+        let mut parsed = StateOptions::default();
+        for option in options.split(',').map(str::trim) {
+            let flag = match option {
+                "failed" => &mut parsed.failed,
+                "passed" => &mut parsed.passed,
+                "all" => &mut parsed.all,
+                "fresh" => &mut parsed.fresh,
+                "new" => &mut parsed.new,
+                "slow" => &mut parsed.slow,
+                "fast" => &mut parsed.fast,
+                "save" => &mut parsed.save,
+                _ => return Err(format!("unknown state option {:?}", option)),
+            };
+            *flag = true;
+        }
+        Ok(parsed)
+    }
+}
+
+/// What the last run of a file showed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileState {
+    /// The run (see `State::generation`) this was recorded in.
+    pub generation: u64,
+    /// 0 when the file passed, otherwise how many tests failed (at least 1).
+    pub last_result: u64,
+    pub elapsed: Duration,
+    /// The file's modification time when it ran.
+    pub mtime: Option<SystemTime>,
+    pub last_run_time: Option<SystemTime>,
+    pub last_pass_time: Option<SystemTime>,
+    pub last_fail_time: Option<SystemTime>,
+}
+
+impl FileState {
+    pub fn passed(&self) -> bool {
+        self.last_result == 0
+    }
+}
+
+/// Results of earlier harness runs, kept between runs in a file like
+/// `prove`'s `.prove`.
+///
+/// ```no_run
+/// use tap_pest::harness::Harness;
+/// use tap_pest::state::State;
+///
+/// # fn run() -> std::io::Result<()> {
+/// let options = "failed,slow,save".parse().unwrap();
+/// let mut state = State::load(".tap-pest-state")?;
+/// let files = state.select(&["t/a.t", "t/b.t"], &options);
+/// let results = Harness::new().run(&files)?;
+/// state.record(&results);
+/// state.save(".tap-pest-state")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct State {
+    /// How many runs have been recorded.
+    pub generation: u64,
+    pub files: BTreeMap<PathBuf, FileState>,
+}
+
+impl State {
+    /// Reads a state file, or starts afresh if there isn't one yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<State> {
+        match fs::read_to_string(path) {
+            Ok(text) => State::parse(&text),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(State::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_yaml())
+    }
+
+    /// The files among `files` to run, in the order to run them.
+    ///
+    /// Files the state knows nothing about count as fresh, and run first
+    /// when ordering by speed.
+    pub fn select<P: AsRef<Path>>(&self, files: &[P], options: &StateOptions) -> Vec<PathBuf> {
+        let mut selected: Vec<PathBuf> = files
+            .iter()
+            .map(|file| file.as_ref().to_path_buf())
+            .filter(|file| {
+                if !options.selects() || options.all {
+                    return true;
+                }
+                let state = self.files.get(file);
+                (options.failed && state.is_some_and(|state| !state.passed()))
+                    || (options.passed && state.is_some_and(FileState::passed))
+                    || (options.fresh && state.is_none_or(|state| is_modified(file, state)))
+            })
+            .collect();
+
+        let elapsed = |file: &PathBuf| self.files.get(file).map(|state| state.elapsed);
+        if options.new {
+            selected.sort_by_key(|file| Reverse(mtime(file)));
+        } else if options.slow {
+            selected.sort_by_key(|file| elapsed(file).map(Reverse));
+        } else if options.fast {
+            selected.sort_by_key(|file| (elapsed(file).is_some(), elapsed(file)));
+        }
+        selected
+    }
+
+    /// Remembers how each file did, as a new generation.
+    pub fn record(&mut self, results: &[FileResult]) {
+        self.generation += 1;
+        for result in results {
+            let state = self.files.entry(result.path.clone()).or_default();
+            state.generation = self.generation;
+            state.last_result = if result.is_ok() {
+                0
+            } else {
+                result.summary.failed.max(1)
+            };
+            state.elapsed = result.duration;
+            state.mtime = mtime(&result.path);
+            state.last_run_time = Some(result.start_time);
+            if result.is_ok() {
+                state.last_pass_time = Some(result.start_time);
+            } else {
+                state.last_fail_time = Some(result.start_time);
+            }
+        }
+    }
+
+    fn to_yaml(&self) -> String {
+        let mut yaml = format!("---\ngeneration: {}\ntests:\n", self.generation);
+        for (path, state) in &self.files {
+            yaml += &format!("  {}:\n", quote(&path.to_string_lossy()));
+            yaml += &format!("    elapsed: {:.6}\n", state.elapsed.as_secs_f64());
+            yaml += &format!("    gen: {}\n", state.generation);
+            let times = [
+                ("last_fail_time", state.last_fail_time),
+                ("last_pass_time", state.last_pass_time),
+            ];
+            for (key, value) in times.iter() {
+                if let Some(value) = value {
+                    yaml += &format!("    {}: {:.6}\n", key, seconds(*value));
+                }
+            }
+            yaml += &format!("    last_result: {}\n", state.last_result);
+            let times = [
+                ("last_run_time", state.last_run_time),
+                ("mtime", state.mtime),
+            ];
+            for (key, value) in times.iter() {
+                if let Some(value) = value {
+                    yaml += &format!("    {}: {:.6}\n", key, seconds(*value));
+                }
+            }
+        }
+        yaml
+    }
+
+    /// Reads back the subset of YAML that `save` writes.
+    fn parse(text: &str) -> io::Result<State> {
+        let mut state = State::default();
+        let mut current = None;
+        for line in text.lines() {
+            let (key, value) = match line.rsplit_once(':') {
+                Some((key, value)) => (key, value.trim()),
+                None => continue,
+            };
+
+            if let Some(key) = key.strip_prefix("    ") {
+                let file = match current.as_ref().and_then(|path| state.files.get_mut(path)) {
+                    Some(file) => file,
+                    None => continue,
+                };
+                match key {
+                    "elapsed" => file.elapsed = duration(number(value)?)?,
+                    "gen" => file.generation = number(value)?,
+                    "last_result" => file.last_result = number(value)?,
+                    "mtime" => file.mtime = Some(time(number(value)?)?),
+                    "last_run_time" => file.last_run_time = Some(time(number(value)?)?),
+                    "last_pass_time" => file.last_pass_time = Some(time(number(value)?)?),
+                    "last_fail_time" => file.last_fail_time = Some(time(number(value)?)?),
+                    _ => {}
+                }
+            } else if let Some(path) = key.strip_prefix("  ") {
+                let path = PathBuf::from(unquote(path));
+                state.files.insert(path.clone(), FileState::default());
+                current = Some(path);
+            } else if key == "generation" {
+                state.generation = number(value)?;
+            }
+        }
+        Ok(state)
+    }
+}
+
+fn mtime(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn is_modified(path: &Path, state: &FileState) -> bool {
+    match (mtime(path), state.mtime) {
+        // The state file only keeps microseconds.
+        (Some(now), Some(then)) => now > then + Duration::from_micros(1),
+        _ => true,
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::formatter::Quiet;
+    use crate::harness::tests::script;
+    use crate::harness::Harness;
+
+    #[test]
+    fn test_options() {
+        let options: StateOptions = "failed, slow,save".parse().unwrap();
+        assert_eq!(
+            options,
+            StateOptions {
+                failed: true,
+                slow: true,
+                save: true,
+                ..StateOptions::default()
+            }
+        );
+        assert!("failed,hot".parse::<StateOptions>().is_err());
+    }
+
+    #[test]
+    fn test_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let fast = script(dir.path(), "fast.t", "1..1\nok 1\n", 0);
+        let failing = script(dir.path(), "failing.t", "1..2\nnot ok 1\nnot ok 2\n", 0);
+        let slow = dir.path().join("slow.t");
+        fs::write(&slow, "#!/bin/sh\nsleep 0.2\necho 1..1\necho ok 1\n").unwrap();
+        fs::set_permissions(&slow, fs::metadata(&fast).unwrap().permissions()).unwrap();
+        let files = [&fast, &failing, &slow];
+
+        let mut state = State::default();
+        state.record(
+            &Harness::new()
+                .formatter(Quiet::new(io::sink()))
+                .run(&files)
+                .unwrap(),
+        );
+        assert_eq!(state.generation, 1);
+        assert_eq!(state.files[&failing].last_result, 2);
+        assert!(state.files[&fast].passed());
+
+        let path = dir.path().join(".tap-pest-state");
+        state.save(&path).unwrap();
+        let loaded = State::load(&path).unwrap();
+        assert_eq!(loaded.files.len(), 3);
+        assert_eq!(loaded.files[&failing].last_result, 2);
+        assert_eq!(
+            loaded.files[&slow].elapsed.as_millis(),
+            state.files[&slow].elapsed.as_millis()
+        );
+        assert!(loaded.files[&fast].last_fail_time.is_none());
+        assert!(loaded.files[&failing].last_fail_time.is_some());
+
+        let new = dir.path().join("new.t");
+        let with_new = [&fast, &failing, &slow, &new];
+        let select = |options: &str| loaded.select(&with_new, &options.parse().unwrap());
+        assert_eq!(select("failed"), vec![failing.clone()]);
+        assert_eq!(select("passed,slow"), vec![slow.clone(), fast.clone()]);
+        assert_eq!(select("fresh"), vec![new.clone()]);
+        assert_eq!(select("all,slow")[..2], [new.clone(), slow.clone()]);
+        let fastest = select("fast");
+        assert_eq!((&fastest[0], &fastest[3]), (&new, &slow));
+        assert_eq!(select("save").len(), 4);
+
+        assert_eq!(
+            State::load(dir.path().join("missing")).unwrap(),
+            State::default()
+        );
+
+        // A saved state whose elapsed time was edited out of range.
+        let mut state = State::default();
+        state.files.insert(
+            PathBuf::from("a.t"),
+            FileState {
+                elapsed: Duration::from_millis(1500),
+                ..FileState::default()
+            },
+        );
+        state.save(&path).unwrap();
+        assert_eq!(State::load(&path).unwrap(), state);
+        let saved = fs::read_to_string(&path).unwrap();
+        for elapsed in ["-1", "inf", "1e300"] {
+            let text = saved.replace("elapsed: 1.500000", &format!("elapsed: {}", elapsed));
+            assert_ne!(text, saved);
+            fs::write(&path, text).unwrap();
+            let error = State::load(&path).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
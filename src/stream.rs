@@ -0,0 +1,385 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+use std::time::Instant;
+
+use crate::document::{parse_stream_line, Line, Span, TapDocument, Unknown};
+use crate::handler::TapHandler;
+
+/// Parses TAP a line at a time from a reader, so events are available while the
+/// producer is still running and memory use doesn't grow with the input.
+///
+/// Each line is matched on its own, which makes this more forgiving than
+/// `TapDocument::parse`: lines the document grammar would reject come back as
+/// `Line::Unknown` instead of failing the whole stream. A YAML block is read up
+/// to its closing `...` and yielded as one `Line::Yaml`; one that is cut off
+/// by an unindented line comes back a line at a time. Invalid UTF-8 is
+/// decoded lossily.
+pub struct StreamParser<R> {
+    reader: R,
+    offset: usize,
+    line: usize,
+    pending: VecDeque<(Line<'static>, Instant)>,
+    /// When the last line was read.
+    arrived: Instant,
+    /// When the previous test point arrived, if test points are being timed.
+    last_test: Option<Instant>,
+}
+
+impl<R: BufRead> StreamParser<R> {
+    pub fn new(reader: R) -> Self {
+        StreamParser {
+            reader,
+            offset: 0,
+            line: 1,
+            pending: VecDeque::new(),
+            arrived: Instant::now(),
+            last_test: None,
+        }
+    }
+
+    /// Sets `TestPoint::duration` to the time since the previous test point
+    /// arrived, or since the parser was created for the first one, unless the
+    /// line carries a node-tap `# time=`. Only meaningful while the producer
+    /// is still running.
+    pub fn timed(mut self) -> Self {
+        self.last_test = Some(Instant::now());
+        self
+    }
+
+    /// The next line, and when the reader handed it over. A YAML block
+    /// arrives with its closing `...`.
+    pub fn next_timed(&mut self) -> Option<io::Result<(Line<'static>, Instant)>> {
+        while self.pending.is_empty() {
+            match self.parse_next() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+
+        self.pending.pop_front().map(Ok)
+    }
+
+    /// Feeds every line to `handler`, then calls `on_end`.
+    pub fn drive<H: TapHandler + ?Sized>(self, handler: &mut H) -> io::Result<()> {
+        for line in self {
+            line?.dispatch(handler);
+        }
+        handler.on_end();
+
+        Ok(())
+    }
+
+    /// The next line, decoded and ending in a newline, and how many bytes of
+    /// the stream it took up.
+    fn read_line(&mut self) -> io::Result<Option<(String, usize)>> {
+        let mut buf = Vec::new();
+        let read = self.reader.read_until(b'\n', &mut buf)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        self.arrived = Instant::now();
+
+        let mut text = String::from_utf8_lossy(&buf).into_owned();
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+
+        Ok(Some((text, read)))
+    }
+
+    /// Parses one line, which comes back as `Line::Unknown` if the grammar
+    /// matched it but it can't be built, like a test number past `u64`.
+    fn parse(&mut self, (text, read): &(String, usize), lines: usize) {
+        let parsed = parse_stream_line(text, self.offset, self.line).unwrap_or_else(|_| {
+            let text = text.strip_suffix('\n').unwrap_or(text);
+            let text = text.strip_suffix('\r').unwrap_or(text);
+            vec![Line::Unknown(Unknown {
+                text: Cow::Borrowed(text),
+                span: Span {
+                    start: self.offset,
+                    end: self.offset + text.len(),
+                    line: self.line,
+                },
+            })]
+        });
+        self.push(parsed, *read, lines);
+    }
+
+    /// Queues `parsed`, which took up `read` bytes of the stream. Spans
+    /// count bytes of the stream, so stay in step with it whatever decoding
+    /// did to the text.
+    fn push(&mut self, parsed: Vec<Line>, read: usize, lines: usize) {
+        for mut line in parsed.into_iter().map(Line::into_owned) {
+            if let (Line::Test(test), Some(last_test)) = (&mut line, &mut self.last_test) {
+                // A time the producer reported itself is more accurate.
+                if test.duration.is_none() {
+                    test.duration = Some(self.arrived.duration_since(*last_test));
+                }
+                *last_test = self.arrived;
+            }
+            self.pending.push_back((line, self.arrived));
+        }
+        self.offset += read;
+        self.line += lines;
+    }
+
+    fn parse_next(&mut self) -> io::Result<bool> {
+        let first = match self.read_line()? {
+            Some(first) => first,
+            None => return Ok(false),
+        };
+        if !is_yaml_marker(&first.0, "---") {
+            self.parse(&first, 1);
+            return Ok(true);
+        }
+
+        // A line that isn't indented or blank ends the block as surely as
+        // `...` does, without waiting for the rest of the stream.
+        let mut block = vec![first];
+        let mut after = None;
+        while let Some(next) = self.read_line()? {
+            if !next.0.starts_with("  ") && !next.0.trim().is_empty() {
+                after = Some(next);
+                break;
+            }
+            let done = is_yaml_marker(&next.0, "...");
+            block.push(next);
+            if done {
+                break;
+            }
+        }
+
+        let text: String = block.iter().map(|(text, _)| text.as_str()).collect();
+        if let Ok(parsed) = parse_stream_line(&text, self.offset, self.line) {
+            let read = block.iter().map(|(_, read)| read).sum();
+            self.push(parsed, read, block.len());
+        } else {
+            // Unterminated block; fall back to taking the lines one at a time.
+            for line in &block {
+                self.parse(line, 1);
+            }
+        }
+        if let Some(after) = after {
+            self.parse(&after, 1);
+        }
+
+        Ok(true)
+    }
+}
+
+impl TapDocument<'static> {
+    /// Reads a whole document with `StreamParser`, which accepts anything
+    /// line-shaped rather than failing on input the grammar rejects.
+    pub fn from_reader<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut document = TapDocument {
+            lines: StreamParser::new(reader).collect::<io::Result<_>>()?,
+        };
+        document.attach_durations();
+
+        Ok(document)
+    }
+}
+
+impl<R: BufRead> Iterator for StreamParser<R> {
+    type Item = io::Result<Line<'static>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_timed()
+            .map(|next| next.map(|(line, _arrived)| line))
+    }
+}
+
+/// YAML blocks are indented two spaces; deeper ones belong to subtests.
+fn is_yaml_marker(line: &str, marker: &str) -> bool {
+    line.strip_prefix("  ")
+        .is_some_and(|rest| rest.trim_end_matches(&['\r', '\n'][..]) == marker)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{Plan, Yaml};
+    use crate::handler::tests::{Recorder, EXAMPLE};
+    use std::io::{BufReader, Read};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Hands over each chunk after its delay, like a program still running.
+    struct Slow(VecDeque<(u64, &'static str)>);
+
+    impl Read for Slow {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let (delay, chunk) = match self.0.pop_front() {
+                Some(next) => next,
+                None => return Ok(0),
+            };
+            thread::sleep(Duration::from_millis(delay));
+            buf[..chunk.len()].copy_from_slice(chunk.as_bytes());
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn test_matches_document() {
+        let mut from_document = Recorder::default();
+        TapDocument::parse(EXAMPLE)
+            .unwrap()
+            .visit(&mut from_document);
+
+        let mut from_stream = Recorder::default();
+        StreamParser::new(EXAMPLE.as_bytes())
+            .drive(&mut from_stream)
+            .unwrap();
+
+        assert_eq!(from_stream.0, from_document.0);
+    }
+
+    #[test]
+    fn test_spans() {
+        let document = TapDocument::parse(EXAMPLE).unwrap();
+        let lines: Vec<_> = StreamParser::new(EXAMPLE.as_bytes())
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(lines, document.lines);
+        assert_eq!(
+            lines[5],
+            Line::Yaml(Yaml {
+                text: Cow::Borrowed("  message: 'boom'\n"),
+                span: Span {
+                    start: 62,
+                    end: 91,
+                    line: 6
+                }
+            })
+        );
+        assert_eq!(lines[6].span().line, 9);
+    }
+
+    #[test]
+    fn test_raw_offsets() {
+        let input = b"ok 1 - caf\xe9\nnot ok 2\n# end";
+        let lines: Vec<_> = StreamParser::new(&input[..])
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(lines[1].span().start, 12);
+        assert_eq!(
+            &input[lines[1].span().start..lines[1].span().end],
+            b"not ok 2"
+        );
+        assert_eq!(lines[2].span().start, 21);
+        assert_eq!(lines[2].span().end, input.len());
+    }
+
+    #[test]
+    fn test_timed() {
+        let slow = Slow(
+            vec![
+                (0, "1..2\n"),
+                (30, "ok 1\n"),
+                (0, "# note\n"),
+                (60, "ok 2\n"),
+            ]
+            .into(),
+        );
+        let mut parser = StreamParser::new(BufReader::new(slow)).timed();
+        let mut lines = Vec::new();
+        while let Some(next) = parser.next_timed() {
+            lines.push(next.unwrap());
+        }
+
+        let durations: Vec<_> = lines
+            .iter()
+            .filter_map(|(line, _)| match line {
+                Line::Test(test) => test.duration,
+                _ => None,
+            })
+            .collect();
+        assert_eq!(durations.len(), 2);
+        assert!(durations[0] >= Duration::from_millis(30));
+        assert!(durations[1] >= Duration::from_millis(60));
+        assert!(lines[3].1.duration_since(lines[2].1) >= Duration::from_millis(60));
+
+        let untimed = TapDocument::from_reader("ok 1\n".as_bytes()).unwrap();
+        assert_eq!(untimed.tests().next().unwrap().duration, None);
+    }
+
+    #[test]
+    fn test_forgiving() {
+        let input = b"ok 1\n\n1..1\n  ---\n  not closed\nnot ok 2 - caf\xe9";
+        let lines: Vec<_> = StreamParser::new(&input[..])
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[1],
+            Line::Plan(Plan {
+                count: 1,
+                directive: None,
+                span: Span {
+                    start: 6,
+                    end: 10,
+                    line: 3
+                }
+            })
+        );
+        assert!(matches!(&lines[2], Line::Unknown(unknown) if unknown.text == "  ---"));
+        assert!(matches!(&lines[3], Line::Unknown(unknown) if unknown.span.line == 5));
+        match &lines[4] {
+            Line::Test(test) => {
+                assert!(!test.ok);
+                assert_eq!(test.description.as_deref(), Some("caf\u{fffd}"));
+            }
+            line => panic!("expected a test, got {:?}", line),
+        }
+    }
+
+    #[test]
+    fn test_unterminated_yaml() {
+        /// Stands for a program that hasn't written anything more yet.
+        struct Stalled;
+
+        impl Read for Stalled {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::WouldBlock, "stalled"))
+            }
+        }
+
+        let input = "ok 1\n  ---\n  message: boom\nok 2\n".as_bytes();
+        let mut parser = StreamParser::new(BufReader::new(input.chain(Stalled)));
+        let lines: Vec<_> = (0..4).map(|_| parser.next().unwrap().unwrap()).collect();
+
+        assert!(matches!(&lines[1], Line::Unknown(unknown) if unknown.text == "  ---"));
+        assert!(matches!(&lines[2], Line::Unknown(unknown) if unknown.span.line == 3));
+        match &lines[3] {
+            Line::Test(test) => assert_eq!(test.number, Some(2)),
+            line => panic!("expected a test, got {:?}", line),
+        }
+        assert_eq!(lines[3].span().line, 4);
+        assert!(parser.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_invalid_number() {
+        let lines: Vec<_> = StreamParser::new(&b"ok 99999999999999999999999\r\nok 2\n"[..])
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(
+            lines[0],
+            Line::Unknown(Unknown {
+                text: Cow::Borrowed("ok 99999999999999999999999"),
+                span: Span {
+                    start: 0,
+                    end: 26,
+                    line: 1
+                }
+            })
+        );
+        assert_eq!(lines[1].span().start, 28);
+    }
+}
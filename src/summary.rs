@@ -0,0 +1,173 @@
+use crate::document::{Bailout, DirectiveKind, Plan, TapDocument, TestPoint};
+use crate::handler::TapHandler;
+
+/// Totals for one TAP stream.
+///
+/// Implements `TapHandler`, so it can be filled in from a `StreamParser` as
+/// well as from a finished document.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Count from the plan, if there was one.
+    pub planned: Option<u64>,
+    /// Reason given by a `1..0 # SKIP` plan.
+    pub skip_all: Option<String>,
+    pub tests: u64,
+    /// `ok` test points, plus `not ok` ones marked TODO.
+    pub passed: u64,
+    /// `not ok` test points not marked TODO.
+    pub failed: u64,
+    pub skipped: u64,
+    pub todo: u64,
+    /// TODO test points that unexpectedly passed.
+    pub todo_passed: u64,
+    /// Reason given by `Bail out!`, empty if none was given.
+    pub bailout: Option<String>,
+}
+
+impl Summary {
+    pub fn from_document(document: &TapDocument) -> Summary {
+        let mut summary = Summary::default();
+        document.visit(&mut summary);
+        summary
+    }
+
+    /// Combined counts of several streams.
+    pub fn total<'s, I: IntoIterator<Item = &'s Summary>>(summaries: I) -> Summary {
+        let mut total = Summary {
+            planned: Some(0),
+            ..Summary::default()
+        };
+        for summary in summaries {
+            total.merge(summary);
+        }
+        total
+    }
+
+    /// Adds another stream's counts to these. The combined plan is the sum of
+    /// both, or missing if either is, and the first bailout wins.
+    pub fn merge(&mut self, other: &Summary) {
+        self.planned = match (self.planned, other.planned) {
+            (Some(planned), Some(other)) => Some(planned + other),
+            _ => None,
+        };
+        self.skip_all = None;
+        self.tests += other.tests;
+        self.passed += other.passed;
+        self.failed += other.failed;
+        self.skipped += other.skipped;
+        self.todo += other.todo;
+        self.todo_passed += other.todo_passed;
+        if self.bailout.is_none() {
+            self.bailout = other.bailout.clone();
+        }
+    }
+
+    /// Whether the number of test points agrees with the plan.
+    pub fn plan_matches(&self) -> bool {
+        self.planned == Some(self.tests)
+    }
+
+    /// No failures, no bailout and the plan was followed.
+    pub fn is_ok(&self) -> bool {
+        self.failed == 0 && self.bailout.is_none() && self.plan_matches()
+    }
+}
+
+impl TapHandler for Summary {
+    fn on_plan(&mut self, plan: &Plan) {
+        self.planned = Some(plan.count);
+        if let Some(directive) = &plan.directive {
+            self.skip_all = Some(directive.reason.as_deref().unwrap_or("").to_string());
+        }
+    }
+
+    fn on_test(&mut self, test: &TestPoint) {
+        self.tests += 1;
+
+        let kind = test.directive.as_ref().map(|directive| directive.kind);
+        match kind {
+            Some(DirectiveKind::Todo) => {
+                self.todo += 1;
+                if test.ok {
+                    self.todo_passed += 1;
+                }
+            }
+            Some(DirectiveKind::Skip) => self.skipped += 1,
+            None => {}
+        }
+
+        if test.ok || kind == Some(DirectiveKind::Todo) {
+            self.passed += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+
+    fn on_bailout(&mut self, bailout: &Bailout) {
+        self.bailout = Some(bailout.reason.as_deref().unwrap_or("").to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::StreamParser;
+
+    const EXAMPLE: &str = r"1..6
+ok 1 - fine
+not ok 2 - broken
+not ok 3 - later # TODO not written
+ok 4 - surprise # TODO not written either
+ok 5 # SKIP no network
+ok 6
+";
+
+    #[test]
+    fn test_from_document() {
+        let summary = Summary::from_document(&TapDocument::parse(EXAMPLE).unwrap());
+
+        assert_eq!(
+            summary,
+            Summary {
+                planned: Some(6),
+                skip_all: None,
+                tests: 6,
+                passed: 5,
+                failed: 1,
+                skipped: 1,
+                todo: 2,
+                todo_passed: 1,
+                bailout: None,
+            }
+        );
+        assert!(summary.plan_matches());
+        assert!(!summary.is_ok());
+    }
+
+    #[test]
+    fn test_from_stream() {
+        let mut summary = Summary::default();
+        StreamParser::new(EXAMPLE.as_bytes())
+            .drive(&mut summary)
+            .unwrap();
+
+        assert_eq!(
+            summary,
+            Summary::from_document(&TapDocument::parse(EXAMPLE).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_is_ok() {
+        let summary = |input| Summary::from_document(&TapDocument::parse(input).unwrap());
+
+        assert!(summary("1..2\nok 1\nnot ok 2 # TODO later\n").is_ok());
+        assert!(!summary("1..3\nok 1\nok 2\n").is_ok());
+        assert!(!summary("1..2\nok 1\nBail out!\n").is_ok());
+        assert!(!summary("ok 1\nok 2\n").is_ok());
+
+        let skipped = summary("1..0 # SKIP no database\n");
+        assert!(skipped.is_ok());
+        assert_eq!(skipped.skip_all.as_deref(), Some("no database"));
+    }
+}
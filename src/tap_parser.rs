@@ -5,7 +5,7 @@ pub(crate) struct TapParser;
 #[cfg(test)]
 mod tests {
     use super::*;
-    use pest::{consumes_to, fails_with, parses_to, Parser};
+    use pest::{consumes_to, fails_with, parses_to};
 
     // examples from https://testanything.org/tap-specification.html
     pub const EXAMPLE_TOP_PLAN_SUCCESS: &str = r#"1..6
@@ -480,6 +480,49 @@ ok 3
         };
     }
 
+    #[test]
+    fn test_tap_time() {
+        parses_to! {
+            parser: TapParser,
+            input: "ok 1 - math # time=3.42ms",
+            rule: Rule::test,
+            tokens: [
+                test(0,25,[
+                    status(0,3),
+                    positiveInteger(3,4),
+                    desc_text(7,12),
+                    time(12,25,[
+                        time_value(19,23)
+                    ])
+                ])
+            ]
+        };
+
+        parses_to! {
+            parser: TapParser,
+            input: "# time=2s",
+            rule: Rule::time,
+            tokens: [
+                time(0,9,[
+                    time_value(7,8)
+                ])
+            ]
+        };
+
+        parses_to! {
+            parser: TapParser,
+            input: "ok 1 - took # time=soon",
+            rule: Rule::test,
+            tokens: [
+                test(0,23,[
+                    status(0,3),
+                    positiveInteger(3,4),
+                    desc_text(7,23)
+                ])
+            ]
+        };
+    }
+
     #[test]
     fn test_tap_status() {
         parses_to! {
@@ -530,6 +573,15 @@ ok 3
             ]
         };
 
+        parses_to! {
+            parser: TapParser,
+            input: "escaped \\# hash # TODO",
+            rule: Rule::desc_text,
+            tokens: [
+                desc_text(0,16)
+            ]
+        };
+
         fails_with! {
             parser: TapParser,
             input: "\n# TODO with stuff after",
@@ -560,6 +612,24 @@ ok 3
             ]
         };
 
+        parses_to! {
+            parser: TapParser,
+            input: "#TODO",
+            rule: Rule::todo_directive,
+            tokens: [
+                todo_directive(0,5)
+            ]
+        };
+
+        fails_with! {
+            parser: TapParser,
+            input: "# TODOS",
+            rule: Rule::todo_directive,
+            positives: vec![Rule::todo_directive],
+            negatives: vec![],
+            pos: 0
+        };
+
         parses_to! {
             parser: TapParser,
             input: "#todo lowercase",
@@ -605,6 +675,15 @@ ok 3
             ]
         };
 
+        parses_to! {
+            parser: TapParser,
+            input: "# skip",
+            rule: Rule::skip_directive,
+            tokens: [
+                skip_directive(0,6)
+            ]
+        };
+
         parses_to! {
             parser: TapParser,
             input: "# skip lower case",
@@ -694,6 +773,38 @@ ok 3
         };
     }
 
+    #[test]
+    fn test_tap_yaml() {
+        parses_to! {
+            parser: TapParser,
+            input: "  ---\n  message: boom\n  severity: fail\n  ...",
+            rule: Rule::yaml,
+            tokens: [
+                yaml(0, 44, [
+                    yaml_text(6, 39)
+                ])
+            ]
+        };
+
+        parses_to! {
+            parser: TapParser,
+            input: "  ---\n  ...",
+            rule: Rule::yaml,
+            tokens: [
+                yaml(0, 11)
+            ]
+        };
+
+        fails_with! {
+            parser: TapParser,
+            input: "---\n...",
+            rule: Rule::yaml,
+            positives: vec![Rule::yaml],
+            negatives: vec![],
+            pos: 0
+        };
+    }
+
     #[test]
     fn test_tap_unknown() {
         parses_to! {
@@ -0,0 +1,579 @@
+//! TAP in the shape common emitters print it, so changes to `tap.pest` can't
+//! quietly stop parsing any of it. Those in `fixtures/captured` are what
+//! Test::More 1.302190 and Node 20's `node:test` printed running the scripts
+//! beside them; the rest are written by hand from each emitter's documented
+//! output and the TAP specification's examples.
+
+use tap_pest::cst::Cst;
+use tap_pest::document::{Line, TapDocument};
+use tap_pest::summary::Summary;
+
+use std::time::Duration;
+
+/// One entry per line: its line number, kind and what it reads back as.
+fn outline(document: &TapDocument) -> Vec<String> {
+    document
+        .lines
+        .iter()
+        .map(|line| {
+            let kind = match line {
+                Line::Version(_) => "version",
+                Line::Plan(_) => "plan",
+                Line::Test(_) => "test",
+                Line::Comment(_) => "comment",
+                Line::Bailout(_) => "bailout",
+                Line::Yaml(yaml) => {
+                    return format!("{} yaml {:?}", line.span().line, yaml.text);
+                }
+                Line::Unknown(_) => "unknown",
+            };
+            format!("{} {} {}", line.span().line, kind, line)
+        })
+        .collect()
+}
+
+/// Parses a fixture both ways, checking the whole-document and streaming
+/// parsers agree and the syntax tree gives back every byte.
+fn parse(input: &str) -> TapDocument<'_> {
+    let document = TapDocument::parse(input).unwrap();
+    let streamed = TapDocument::from_reader(input.as_bytes()).unwrap();
+    assert_eq!(streamed, document);
+    assert_eq!(Cst::parse(input).unwrap().to_string(), input);
+    document
+}
+
+#[test]
+fn test_more() {
+    let document = parse(include_str!("fixtures/test_more.tap"));
+
+    assert_eq!(
+        outline(&document),
+        vec![
+            "1 unknown     # Subtest: connection",
+            "2 unknown     ok 1 - connects",
+            "3 unknown     ok 2 - authenticates",
+            "4 unknown     1..2",
+            "5 test ok 1 - connection",
+            "6 test ok 2 - greeting is set",
+            "7 test not ok 3 - greeting matches",
+            "8 comment #   Failed test 'greeting matches'",
+            "9 comment #   at t/greeting.t line 14.",
+            "10 comment #          got: 'hallo'",
+            "11 comment #     expected: 'hello'",
+            "12 test ok 4 # SKIP no network available",
+            "13 test not ok 5 - unicode names # TODO not implemented yet",
+            "14 comment #   Failed (TODO) test 'unicode names'",
+            "15 comment #   at t/greeting.t line 22.",
+            "16 test ok 6 - cleanup",
+            "17 plan 1..6",
+            "18 comment # Looks like you failed 1 test of 6.",
+        ]
+    );
+    assert_eq!(
+        Summary::from_document(&document),
+        Summary {
+            planned: Some(6),
+            skip_all: None,
+            tests: 6,
+            passed: 5,
+            failed: 1,
+            skipped: 1,
+            todo: 1,
+            todo_passed: 0,
+            bailout: None,
+        }
+    );
+
+    let failures = document.failures();
+    assert_eq!(failures.len(), 2);
+    assert_eq!(failures[0].diagnostic.line, Some(14));
+    assert_eq!(failures[0].diagnostic.got.as_deref(), Some("hallo"));
+    assert_eq!(failures[0].diagnostic.expected.as_deref(), Some("hello"));
+    assert_eq!(
+        failures[1].diagnostic.name.as_deref(),
+        Some("unicode names")
+    );
+}
+
+#[test]
+fn node_tap() {
+    let document = parse(include_str!("fixtures/node_tap.tap"));
+
+    assert_eq!(
+        outline(&document),
+        vec![
+            "1 version TAP version 13",
+            "2 comment # Subtest: math",
+            "3 unknown     1..2",
+            "4 unknown     ok 1 - addition works",
+            "5 unknown     ok 2 - subtraction works",
+            "6 test ok 1 - math # time=3.42ms",
+            "8 comment # Subtest: strings",
+            "9 unknown     1..1",
+            "10 unknown     not ok 1 - should be equal",
+            "11 unknown       ---",
+            "12 unknown       found: foo",
+            "13 unknown       wanted: bar",
+            "14 unknown       at:",
+            "15 unknown         line: 7",
+            "16 unknown         column: 5",
+            "17 unknown         file: test/strings.js",
+            "18 unknown       stack: |",
+            "19 unknown         Test.<anonymous> (test/strings.js:7:5)",
+            "20 unknown       ...",
+            "22 unknown     # failed 1 test",
+            "23 test not ok 2 - strings # time=5.1ms",
+            "24 yaml \"  duration_ms: 5.1\\n\"",
+            "28 plan 1..2",
+            "29 comment # failed 1 of 2 tests",
+            "30 comment # time=20.4ms",
+        ]
+    );
+    assert_eq!(
+        Summary::from_document(&document),
+        Summary {
+            planned: Some(2),
+            skip_all: None,
+            tests: 2,
+            passed: 1,
+            failed: 1,
+            skipped: 0,
+            todo: 0,
+            todo_passed: 0,
+            bailout: None,
+        }
+    );
+
+    let durations: Vec<_> = document.tests().map(|test| test.duration).collect();
+    assert_eq!(
+        durations,
+        vec![
+            Some(Duration::from_micros(3420)),
+            Some(Duration::from_micros(5100))
+        ]
+    );
+    assert_eq!(document.duration(), Some(Duration::from_micros(20400)));
+}
+
+#[test]
+fn bats() {
+    let document = parse(include_str!("fixtures/bats.tap"));
+
+    assert_eq!(
+        outline(&document),
+        vec![
+            "1 plan 1..4",
+            "2 test ok 1 - addition using bc",
+            "3 test ok 2 - addition using dc # SKIP",
+            "4 test not ok 3 - subtraction fails",
+            "5 comment # (in test file test/math.bats, line 12)",
+            "6 comment #   `[ \"$result\" -eq 1 ]' failed",
+            "7 test ok 4 - multiplication # SKIP not on CI",
+        ]
+    );
+    assert_eq!(
+        Summary::from_document(&document),
+        Summary {
+            planned: Some(4),
+            skip_all: None,
+            tests: 4,
+            passed: 3,
+            failed: 1,
+            skipped: 2,
+            todo: 0,
+            todo_passed: 0,
+            bailout: None,
+        }
+    );
+}
+
+#[test]
+fn pytest_tap() {
+    let document = parse(include_str!("fixtures/pytest_tap.tap"));
+
+    assert_eq!(
+        outline(&document),
+        vec![
+            "1 version TAP version 13",
+            "2 plan 1..4",
+            "3 test ok 1 - tests/test_math.py::test_add",
+            "4 test not ok 2 - tests/test_math.py::test_sub",
+            "5 comment # def test_sub():",
+            "6 comment # >       assert 1 - 1 == 1",
+            "7 comment # E       assert 0 == 1",
+            "8 comment #",
+            "9 comment # tests/test_math.py:6: AssertionError",
+            "10 test ok 3 - tests/test_math.py::test_mul # SKIP not ready",
+            "11 test not ok 4 - tests/test_math.py::test_div # TODO expected failure: division is hard",
+        ]
+    );
+    assert_eq!(
+        Summary::from_document(&document),
+        Summary {
+            planned: Some(4),
+            skip_all: None,
+            tests: 4,
+            passed: 3,
+            failed: 1,
+            skipped: 1,
+            todo: 1,
+            todo_passed: 0,
+            bailout: None,
+        }
+    );
+}
+
+#[test]
+fn libtap() {
+    let document = parse(include_str!("fixtures/libtap.tap"));
+
+    assert_eq!(
+        outline(&document),
+        vec![
+            "1 plan 1..5",
+            "2 test ok 1 - initialised",
+            "3 test not ok 2 - buffer is empty",
+            "4 comment #   Failed test 'buffer is empty'",
+            "5 comment #   at tests/buffer.c line 42.",
+            "6 test ok 3 # SKIP no network",
+            "7 test not ok 4 - reads config # TODO config parser",
+            "8 comment #   Failed (TODO) test 'reads config'",
+            "9 comment #   at tests/config.c line 17.",
+            "10 test ok 5 - cleanup",
+            "11 comment # Looks like you failed 1 test of 5 run.",
+        ]
+    );
+    assert_eq!(
+        Summary::from_document(&document),
+        Summary {
+            planned: Some(5),
+            skip_all: None,
+            tests: 5,
+            passed: 4,
+            failed: 1,
+            skipped: 1,
+            todo: 1,
+            todo_passed: 0,
+            bailout: None,
+        }
+    );
+    assert_eq!(
+        document.failures()[0].diagnostic.file.as_deref(),
+        Some("tests/buffer.c")
+    );
+}
+
+#[test]
+fn tap14_yaml() {
+    let document = parse(include_str!("fixtures/tap14_yaml.tap"));
+
+    assert_eq!(
+        outline(&document),
+        vec![
+            "1 version TAP version 14",
+            "2 plan 1..4",
+            "3 test ok 1 - Input file opened",
+            "4 test not ok 2 - First line of the input valid",
+            "5 yaml \"  message: 'First line invalid'\\n  severity: fail\\n  data:\\n    got: 'Flirble'\\n    expect: 'Fnible'\\n\"",
+            "12 test ok 3 - Read the rest of the file",
+            "13 test not ok 4 - Summarized correctly # TODO Not written yet",
+            "14 yaml \"  message: \\\"Can't make summary yet\\\"\\n  severity: todo\\n\"",
+        ]
+    );
+    assert_eq!(
+        Summary::from_document(&document),
+        Summary {
+            planned: Some(4),
+            skip_all: None,
+            tests: 4,
+            passed: 3,
+            failed: 1,
+            skipped: 0,
+            todo: 1,
+            todo_passed: 0,
+            bailout: None,
+        }
+    );
+}
+
+#[test]
+fn tap14_subtests() {
+    let document = parse(include_str!("fixtures/tap14_subtests.tap"));
+
+    assert_eq!(
+        outline(&document),
+        vec![
+            "1 version TAP version 14",
+            "2 plan 1..2",
+            "3 comment # Subtest: foo.tap",
+            "4 unknown     1..2",
+            "5 unknown     ok 1",
+            "6 unknown     ok 2 - this passed",
+            "7 test ok 1 - foo.tap",
+            "9 comment # Subtest: bar.tap",
+            "10 unknown     ok 1 - object should be a Bar",
+            "11 unknown     not ok 2 - object.isBar should return true",
+            "12 unknown       ---",
+            "13 unknown       found: false",
+            "14 unknown       wanted: true",
+            "15 unknown       at:",
+            "16 unknown         file: test/bar.ts",
+            "17 unknown         line: 43",
+            "18 unknown         column: 8",
+            "19 unknown       ...",
+            "20 unknown     ok 3 - object can bar bears # TODO",
+            "21 unknown     1..3",
+            "22 test not ok 2 - bar.tap",
+            "23 yaml \"  fail: 1\\n  todo: 1\\n\"",
+        ]
+    );
+    assert_eq!(
+        Summary::from_document(&document),
+        Summary {
+            planned: Some(2),
+            skip_all: None,
+            tests: 2,
+            passed: 1,
+            failed: 1,
+            skipped: 0,
+            todo: 0,
+            todo_passed: 0,
+            bailout: None,
+        }
+    );
+}
+
+/// `perl test_more.t 2>&1`, with diagnostics merged in as `prove --merge`
+/// does.
+#[test]
+fn captured_test_more() {
+    let document = parse(include_str!("fixtures/captured/test_more.tap"));
+
+    assert_eq!(
+        outline(&document),
+        vec![
+            "1 comment # talking to a fake server",
+            "2 test ok 1 - connects",
+            "3 comment # Subtest: greeting",
+            "4 unknown     1..2",
+            "5 unknown     ok 1 - is set",
+            "6 unknown     not ok 2 - matches",
+            "7 unknown     #   Failed test 'matches'",
+            "8 unknown     #   at test_more.t line 10.",
+            "9 unknown     #          got: 'hallo'",
+            "10 unknown     #     expected: 'hello'",
+            "11 unknown     # Looks like you failed 1 test of 2.",
+            "12 test not ok 2 - greeting",
+            "13 comment #   Failed test 'greeting'",
+            "14 comment #   at test_more.t line 11.",
+            "15 test not ok 3 - lists match",
+            "16 comment #   Failed test 'lists match'",
+            "17 comment #   at test_more.t line 12.",
+            "18 comment #     Structures begin differing at:",
+            "19 comment #          $got->[1] = '2'",
+            "20 comment #     $expected->[1] = '3'",
+            "21 test not ok 4 - handles \\# in names # TODO not written yet",
+            "22 comment #   Failed (TODO) test 'handles # in names'",
+            "23 comment #   at test_more.t line 15.",
+            "24 test ok 5 # SKIP no database",
+            "25 comment # finishing up",
+            "26 plan 1..5",
+            "27 comment # Looks like you failed 2 tests of 5.",
+        ]
+    );
+    assert_eq!(
+        Summary::from_document(&document),
+        Summary {
+            planned: Some(5),
+            skip_all: None,
+            tests: 5,
+            passed: 3,
+            failed: 2,
+            skipped: 1,
+            todo: 1,
+            todo_passed: 0,
+            bailout: None,
+        }
+    );
+    let todo = document.tests().nth(3).unwrap();
+    assert_eq!(todo.description.as_deref(), Some("handles # in names"));
+}
+
+/// `node --test --test-reporter=tap node_test.mjs`.
+#[test]
+fn captured_node_test() {
+    let document = parse(include_str!("fixtures/captured/node_test.tap"));
+    let outline = outline(&document);
+
+    assert_eq!(
+        outline[..4],
+        [
+            "1 version TAP version 13",
+            "2 comment # Subtest: adds",
+            "3 test ok 1 - adds",
+            "4 yaml \"  duration_ms: 1.462576\\n\"",
+        ]
+    );
+    // The nested tests, with their own deeper YAML, up to the plan that
+    // closes them.
+    assert!(outline[5..37]
+        .iter()
+        .all(|line| line.contains(" unknown     ")));
+    assert_eq!(outline[36], "39 unknown     1..2");
+    assert_eq!(outline[37], "40 test not ok 2 - parser");
+    assert!(outline[38].starts_with("41 yaml \"  duration_ms: 2.635286\\n"));
+    assert_eq!(outline[40], "49 test ok 3 - network # SKIP offline");
+    assert_eq!(outline[43], "54 test not ok 4 - caching # TODO not written");
+    assert_eq!(outline[45], "72 plan 1..4");
+    assert_eq!(
+        outline.last().unwrap(),
+        "80 comment # duration_ms 174.104201"
+    );
+    let tests: Vec<_> = document.tests().collect();
+    assert_eq!(tests[1].duration, Some(Duration::from_nanos(2_635_286)));
+    assert_eq!(
+        Summary::from_document(&document),
+        Summary {
+            planned: Some(4),
+            skip_all: None,
+            tests: 4,
+            passed: 3,
+            failed: 1,
+            skipped: 1,
+            todo: 1,
+            todo_passed: 0,
+            bailout: None,
+        }
+    );
+}
+
+// The grammar fixes that came with this suite, each with the smallest input
+// that shows it. Before them, `tap` could stop matching partway through and
+// `TapDocument::parse` succeeded with the rest of the input silently missing;
+// it is now anchored at the end of the input, and each outline below runs to
+// the fixture's last line.
+
+/// Blank lines used to end the document there.
+#[test]
+fn grammar_blank_lines() {
+    let document = parse(include_str!("fixtures/grammar/blank_lines.tap"));
+
+    assert_eq!(
+        outline(&document),
+        vec![
+            "1 plan 1..3",
+            "2 test ok 1 - before",
+            "4 test ok 2 - after a blank line",
+            "6 test ok 3 - and another",
+        ]
+    );
+}
+
+/// `# SKIP` and `# TODO` need no reason; without one the test point, and
+/// everything after it, used to be dropped.
+#[test]
+fn grammar_bare_directives() {
+    let document = parse(include_str!("fixtures/grammar/bare_directives.tap"));
+
+    assert_eq!(
+        outline(&document),
+        vec![
+            "1 plan 1..3",
+            "2 test ok 1 # SKIP",
+            "3 test not ok 2 # TODO",
+            "4 test ok 3 - reasons are optional",
+        ]
+    );
+    let summary = Summary::from_document(&document);
+    assert_eq!((summary.skipped, summary.todo, summary.failed), (1, 1, 0));
+}
+
+/// A `#` only starts a directive when TODO or SKIP follows; any other used
+/// to end the document. Written back out, each is escaped.
+#[test]
+fn grammar_hash_in_description() {
+    let document = parse(include_str!("fixtures/grammar/hash_in_description.tap"));
+
+    assert_eq!(
+        outline(&document),
+        vec![
+            "1 plan 1..3",
+            "2 test ok 1 - closes issue \\#42",
+            "3 test ok 2 - C\\# and F\\# # SKIP no dotnet",
+            "4 test ok 3 - after them",
+        ]
+    );
+    let tests: Vec<_> = document.tests().collect();
+    assert_eq!(tests[1].description.as_deref(), Some("C# and F#"));
+    assert!(tests[1].directive.is_some());
+}
+
+/// A version line was only recognised before a leading plan, and came back
+/// as an unknown line before a trailing one.
+#[test]
+fn grammar_version_trailing_plan() {
+    let document = parse(include_str!("fixtures/grammar/version_trailing_plan.tap"));
+
+    assert_eq!(
+        outline(&document),
+        vec![
+            "1 version TAP version 13",
+            "2 test ok 1 - first",
+            "3 test ok 2 - second",
+            "4 plan 1..2",
+        ]
+    );
+}
+
+/// A line before a trailing plan with "ok" in the middle used to fail to
+/// parse at the "ok".
+#[test]
+fn grammar_ok_in_unknown() {
+    let document = parse(include_str!("fixtures/grammar/ok_in_unknown.tap"));
+
+    assert_eq!(
+        outline(&document),
+        vec![
+            "1 unknown make: build looks ok to me",
+            "2 test ok 1 - built",
+            "3 plan 1..1",
+        ]
+    );
+}
+
+/// YAML indented deeper than two spaces belongs to a subtest, and used to be
+/// taken as this level's block.
+#[test]
+fn grammar_nested_yaml() {
+    let document = parse(include_str!("fixtures/grammar/nested_yaml.tap"));
+
+    assert_eq!(
+        outline(&document),
+        vec![
+            "1 plan 1..1",
+            "2 comment # Subtest: inner",
+            "3 unknown     1..1",
+            "4 unknown     not ok 1 - deep",
+            "5 unknown       ---",
+            "6 unknown       message: inner",
+            "7 unknown       ...",
+            "8 test not ok 1 - inner",
+            "9 yaml \"  message: outer\\n\"",
+        ]
+    );
+}
+
+/// The last line may lack its newline; it used to be dropped.
+#[test]
+fn grammar_no_final_newline() {
+    let document = parse(include_str!("fixtures/grammar/no_final_newline.tap"));
+
+    assert_eq!(
+        outline(&document),
+        vec![
+            "1 plan 1..2",
+            "2 test ok 1",
+            "3 test ok 2 - no newline after me",
+        ]
+    );
+}
@@ -0,0 +1,21 @@
+import { test } from 'node:test';
+import assert from 'node:assert';
+
+test('adds', () => {
+  assert.strictEqual(1 + 1, 2);
+});
+
+test('parser', async (t) => {
+  await t.test('reads numbers', () => {
+    assert.strictEqual(Number('12'), 12);
+  });
+  await t.test('reads words', () => {
+    assert.strictEqual(String(12), 'twelve');
+  });
+});
+
+test('network', { skip: 'offline' }, () => {});
+
+test('caching', { todo: 'not written' }, () => {
+  assert.fail('no cache');
+});
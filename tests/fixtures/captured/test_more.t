@@ -0,0 +1,22 @@
+use strict;
+use warnings;
+use Test::More;
+
+note 'talking to a fake server';
+ok 1, 'connects';
+subtest 'greeting' => sub {
+    plan tests => 2;
+    is 'hello', 'hello', 'is set';
+    is 'hallo', 'hello', 'matches';
+};
+is_deeply [1, 2], [1, 3], 'lists match';
+TODO: {
+    local $TODO = 'not written yet';
+    ok 0, 'handles # in names';
+}
+SKIP: {
+    skip 'no database', 1;
+    ok 1, 'queries';
+}
+diag 'finishing up';
+done_testing;